@@ -0,0 +1,52 @@
+//! Compares shadow ray queries through `hit` against the any-hit `occluded`
+//! query, on a scene laid out like the final scene of the book.
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use raytracer::materials::Lambertian;
+use raytracer::{color, point3, Hittable, HittableList, Ray, Sphere, Vec3};
+use std::sync::Arc;
+
+fn scene() -> HittableList {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut world = HittableList::new();
+    let material = Arc::new(Lambertian::new(color(0.5, 0.5, 0.5)));
+    world.add(Box::new(Sphere::new(point3(0., -1e3, 0.), 1000., material.clone())));
+    for a in -11..11 {
+        for b in -11..11 {
+            let center = point3(a as f64 + 0.9 * rng.gen::<f64>(), 0.2, b as f64 + 0.9 * rng.gen::<f64>());
+            world.add(Box::new(Sphere::new(center, 0.2, material.clone())));
+        }
+    }
+    world
+}
+
+/// Rays from points on the ground towards a light above the scene
+fn shadow_rays(count: usize) -> Vec<(Ray, f64)> {
+    let mut rng = StdRng::seed_from_u64(1);
+    let light = point3(0., 20., 0.);
+    (0..count)
+        .map(|_| {
+            let origin = point3(rng.gen_range(-11., 11.), 0., rng.gen_range(-11., 11.));
+            let to_light: Vec3 = light - origin;
+            let distance = to_light.length();
+            (Ray::new(origin, to_light / distance), distance)
+        })
+        .collect()
+}
+
+fn bench_shadow_rays(c: &mut Criterion) {
+    let world = scene();
+    let rays = shadow_rays(1000);
+    let mut group = c.benchmark_group("shadow_rays");
+    group.bench_function("hit", |b| {
+        b.iter(|| rays.iter().filter(|(r, d)| world.hit(black_box(r), 0.001, *d).is_some()).count())
+    });
+    group.bench_function("occluded", |b| {
+        b.iter(|| rays.iter().filter(|(r, d)| world.occluded(black_box(r), 0.001, *d)).count())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_shadow_rays);
+criterion_main!(benches);
@@ -0,0 +1,102 @@
+use crate::{luminance, Color};
+
+/// Stops sampling a pixel once its estimated relative error is small enough
+///
+/// Samples are taken in batches of `min_samples`; after each batch the
+/// standard error of the mean luminance, relative to the mean, is compared
+/// against `threshold`. The renderer's `samples_per_pixel` is the upper bound.
+/// Batches hold at least two samples, so that the variance can be estimated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveSampling {
+    min_samples: u32,
+    threshold: f64,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        Self { min_samples: 16, threshold: 0.01 }
+    }
+}
+
+impl AdaptiveSampling {
+    pub fn new(min_samples: u32, threshold: f64) -> Self {
+        Self { min_samples: min_samples.max(2), threshold }
+    }
+    pub fn min_samples(&self) -> u32 {
+        self.min_samples
+    }
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    pub fn is_converged(&self, stats: &PixelStatistics) -> bool {
+        stats.count() >= self.min_samples && stats.relative_error() < self.threshold
+    }
+}
+
+/// Running mean and variance of a pixel's sample luminance (Welford's algorithm)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PixelStatistics {
+    count: u32,
+    mean: f64,
+    m2: f64,
+}
+
+impl PixelStatistics {
+    pub fn add(&mut self, c: Color) {
+        let x = luminance(c);
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+    /// Unbiased sample variance
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            0.
+        } else {
+            self.m2 / (self.count - 1) as f64
+        }
+    }
+    /// Standard error of the mean relative to the mean
+    ///
+    /// The mean is floored so that nearly black pixels do not demand an
+    /// unbounded number of samples.
+    pub fn relative_error(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        (self.variance() / self.count as f64).sqrt() / self.mean.max(0.01)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_statistics() {
+        let mut stats = PixelStatistics::default();
+        for &x in &[1., 2., 3., 4.] {
+            stats.add(Color::new(x, x, x));
+        }
+        assert!((stats.mean() - 2.5).abs() < 1e-9);
+        assert!((stats.variance() - 5. / 3.).abs() < 1e-9);
+    }
+    #[test]
+    fn test_constant_pixel_converges() {
+        let adaptive = AdaptiveSampling::new(4, 0.01);
+        let mut stats = PixelStatistics::default();
+        for _ in 0..3 {
+            stats.add(Color::new(0.5, 0.7, 1.0));
+        }
+        assert!(!adaptive.is_converged(&stats));
+        stats.add(Color::new(0.5, 0.7, 1.0));
+        assert!(adaptive.is_converged(&stats));
+    }
+}
@@ -0,0 +1,295 @@
+use crate::{CameraBuilder, Environment, Hittable, HittableList, Light, Point3, Transform, Transformed, Vec3};
+use std::fmt;
+use std::ops::{Add, Mul, Sub};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Value that can be interpolated between keyframes
+pub trait Animatable: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f64, Output = Self> {}
+
+impl<T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T>> Animatable for T {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    /// Smooth curve through the keys, with tangents from the neighbouring keys
+    CatmullRom,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe<T> {
+    pub time: f64,
+    pub value: T,
+}
+
+/// Keyframed value over time
+///
+/// Before the first key and after the last one the track holds the value of
+/// that key.
+#[derive(Debug, Clone)]
+pub struct Track<T> {
+    keys: Vec<Keyframe<T>>,
+    pub interpolation: Interpolation,
+}
+
+impl<T: Animatable> Track<T> {
+    pub fn new(interpolation: Interpolation) -> Self {
+        Self { keys: Vec::new(), interpolation }
+    }
+    pub fn constant(value: T) -> Self {
+        Self::new(Interpolation::Linear).with_key(0., value)
+    }
+    /// Adds a key, replacing any key at the same time
+    pub fn with_key(mut self, time: f64, value: T) -> Self {
+        self.add_key(time, value);
+        self
+    }
+    pub fn add_key(&mut self, time: f64, value: T) {
+        let idx = self.keys.partition_point(|k| k.time < time);
+        let key = Keyframe { time, value };
+        if self.keys.get(idx).is_some_and(|k| k.time == time) {
+            self.keys[idx] = key;
+        } else {
+            self.keys.insert(idx, key);
+        }
+    }
+    pub fn keys(&self) -> &[Keyframe<T>] {
+        &self.keys
+    }
+
+    /// Value at `time`; panics if the track has no keys
+    pub fn sample(&self, time: f64) -> T {
+        let keys = &self.keys;
+        assert!(!keys.is_empty(), "sampled an empty animation track");
+        let i = keys.partition_point(|k| k.time <= time);
+        if i == 0 {
+            return keys[0].value;
+        }
+        if i == keys.len() {
+            return keys[i - 1].value;
+        }
+        let (k1, k2) = (&keys[i - 1], &keys[i]);
+        let dt = k2.time - k1.time;
+        let s = (time - k1.time) / dt;
+        match self.interpolation {
+            Interpolation::Linear => k1.value + (k2.value - k1.value) * s,
+            Interpolation::CatmullRom => {
+                // Missing neighbours at the ends are replaced by the end keys
+                let k0 = &keys[(i - 1).saturating_sub(1)];
+                let k3 = &keys[(i + 1).min(keys.len() - 1)];
+                let m1 = (k2.value - k0.value) * (dt / (k2.time - k0.time));
+                let m2 = (k3.value - k1.value) * (dt / (k3.time - k1.time));
+                let (s2, s3) = (s * s, s * s * s);
+                k1.value * (2. * s3 - 3. * s2 + 1.)
+                    + m1 * (s3 - 2. * s2 + s)
+                    + k2.value * (3. * s2 - 2. * s3)
+                    + m2 * (s3 - s2)
+            }
+        }
+    }
+}
+
+/// Animated translation, rotation (degrees) and scale of an object
+///
+/// Scale keys must be positive. A Catmull-Rom scale track can overshoot
+/// below its smallest key, so the scale is kept at or above that key.
+#[derive(Debug, Clone)]
+pub struct TransformTrack {
+    pub translation: Track<Vec3>,
+    pub rotation: Track<Vec3>,
+    pub scale: Track<f64>,
+}
+
+impl Default for TransformTrack {
+    fn default() -> Self {
+        Self {
+            translation: Track::constant(Vec3::default()),
+            rotation: Track::constant(Vec3::default()),
+            scale: Track::constant(1.),
+        }
+    }
+}
+
+impl TransformTrack {
+    pub fn at(&self, time: f64) -> Transform {
+        Transform {
+            translation: self.translation.sample(time),
+            rotation: self.rotation.sample(time),
+            scale: self.scale.sample(time).max(self.min_scale()),
+        }
+    }
+    fn min_scale(&self) -> f64 {
+        self.scale.keys().iter().map(|k| k.value).fold(f64::INFINITY, f64::min)
+    }
+    /// Checks that every scale key is positive
+    pub fn validate(&self) -> Result<(), String> {
+        match self.scale.keys().iter().find(|k| !(k.value > 0. && k.value.is_finite())) {
+            Some(k) => Err(format!("scale {} at time {} is not positive", k.value, k.time)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Animated camera position, target and vertical field of view
+#[derive(Debug, Clone)]
+pub struct CameraTrack {
+    pub position: Track<Point3>,
+    pub target: Track<Point3>,
+    pub vfov: Track<f64>,
+}
+
+impl CameraTrack {
+    /// Sets the animated parameters of `base` to their values at `time`
+    pub fn builder_at(&self, time: f64, base: CameraBuilder) -> CameraBuilder {
+        base.position(self.position.sample(time))
+            .target(self.target.sample(time))
+            .vfov(self.vfov.sample(time))
+    }
+}
+
+/// Scene whose objects are built once and moved per frame
+pub struct AnimatedScene {
+    objects: Vec<(Arc<dyn Hittable + Sync + Send>, Option<TransformTrack>)>,
+    lights: Vec<Arc<dyn Light + Sync + Send>>,
+    environment: Arc<dyn Environment + Sync + Send>,
+}
+
+impl AnimatedScene {
+    /// Takes over the objects, lights and environment of a scene, initially without animation
+    pub fn from_list(mut list: HittableList) -> Self {
+        let objects = list.drain(..).map(|o| (Arc::from(o), None)).collect();
+        Self { objects, lights: list.lights().to_vec(), environment: list.environment().clone() }
+    }
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+    pub fn add(&mut self, object: Box<dyn Hittable + Sync + Send>, track: Option<TransformTrack>) {
+        self.objects.push((Arc::from(object), track));
+    }
+    /// Animates the object at `index`, in the order the objects were added;
+    /// fails if the track has a scale key that is not positive
+    pub fn animate(&mut self, index: usize, track: TransformTrack) -> Result<(), String> {
+        track.validate()?;
+        self.objects[index].1 = Some(track);
+        Ok(())
+    }
+
+    /// Scene at `time`, sharing the objects with this one
+    pub fn scene_at(&self, time: f64) -> HittableList {
+        let mut scene = HittableList::new();
+        for (object, track) in self.objects.iter() {
+            match track {
+                Some(track) => scene.add(Box::new(Transformed::new(object.clone(), track.at(time)))),
+                None => scene.add(Box::new(object.clone())),
+            }
+        }
+        for light in self.lights.iter() {
+            scene.add_light(light.clone());
+        }
+        scene.set_environment(self.environment.clone());
+        scene
+    }
+}
+
+/// Inclusive range of frame numbers, written as `first-last` or a single frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameRange {
+    pub first: u32,
+    pub last: u32,
+}
+
+impl FrameRange {
+    pub fn frames(&self) -> impl Iterator<Item = u32> {
+        self.first..=self.last
+    }
+}
+
+impl FromStr for FrameRange {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |v: &str| v.trim().parse::<u32>().map_err(|e| format!("invalid frame '{}': {}", v, e));
+        let (first, last) = match s.split_once('-') {
+            Some((a, b)) => (parse(a)?, parse(b)?),
+            None => {
+                let frame = parse(s)?;
+                (frame, frame)
+            }
+        };
+        if last < first {
+            return Err(format!("frame range '{}' ends before it starts", s));
+        }
+        Ok(FrameRange { first, last })
+    }
+}
+
+impl FromStr for Interpolation {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "linear" => Ok(Interpolation::Linear),
+            "catmull-rom" | "catmullrom" => Ok(Interpolation::CatmullRom),
+            _ => Err(format!("unknown interpolation '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Interpolation::Linear => "linear",
+            Interpolation::CatmullRom => "catmull-rom",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_linear_track() {
+        let track = Track::new(Interpolation::Linear).with_key(2., 10.).with_key(0., 0.);
+        assert_eq!(track.sample(-1.), 0.);
+        assert_eq!(track.sample(0.5), 2.5);
+        assert_eq!(track.sample(3.), 10.);
+    }
+    #[test]
+    fn test_catmull_rom_passes_through_keys() {
+        let mut track = Track::new(Interpolation::CatmullRom);
+        for (t, v) in &[(0., 0.), (1., 1.), (2., 4.), (3., 9.)] {
+            track.add_key(*t, *v);
+        }
+        for (t, v) in &[(0., 0.), (1., 1.), (2., 4.), (3., 9.)] {
+            assert!((track.sample(*t) - v).abs() < 1e-12);
+        }
+        // Curves through the middle keys rather than following straight segments
+        let mid = track.sample(1.5);
+        assert!(mid < 2.5 && mid > 2.);
+    }
+    #[test]
+    fn test_scale_stays_positive() {
+        let mut scale = Track::new(Interpolation::CatmullRom);
+        for (t, v) in &[(0., 1.), (1., 0.05), (2., 0.05), (3., 1.)] {
+            scale.add_key(*t, *v);
+        }
+        // The curve itself dips below zero between the small keys
+        assert!(scale.sample(1.5) < 0.);
+        let track = TransformTrack { scale, ..TransformTrack::default() };
+        assert!(track.validate().is_ok());
+        assert_eq!(track.at(1.5).scale, 0.05);
+        let shrunk = TransformTrack { scale: Track::constant(0.), ..TransformTrack::default() };
+        let mut scene = AnimatedScene::from_list(HittableList::new());
+        scene.add(Box::new(crate::SimpleSphere::new(Point3::default(), 1.)), None);
+        assert!(scene.animate(0, shrunk).is_err());
+    }
+    #[test]
+    fn test_frame_range() {
+        assert_eq!("3-7".parse::<FrameRange>().unwrap().frames().count(), 5);
+        assert_eq!("4".parse::<FrameRange>(), Ok(FrameRange { first: 4, last: 4 }));
+        assert!("7-3".parse::<FrameRange>().is_err());
+    }
+}
@@ -0,0 +1,317 @@
+use crate::{heatmap_color, mix, Color, Point3, ToneMap, Vec3};
+use image::RgbImage;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Auxiliary per-pixel quantity taken from the first surface hit by camera rays
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AovKind {
+    /// Distance from the camera to the hit point
+    Depth,
+    /// World space shading normal
+    Normal,
+    /// Surface color of the material
+    Albedo,
+    /// World space hit point
+    Position,
+    MaterialId,
+    ObjectId,
+}
+
+impl AovKind {
+    pub const ALL: [AovKind; 6] = [
+        AovKind::Depth,
+        AovKind::Normal,
+        AovKind::Albedo,
+        AovKind::Position,
+        AovKind::MaterialId,
+        AovKind::ObjectId,
+    ];
+}
+
+/// First hit of a single camera sample
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AovSample {
+    /// Infinite when the sample hit nothing
+    pub depth: f64,
+    pub normal: Vec3,
+    pub albedo: Color,
+    pub position: Point3,
+    /// One plus the index of the material in the scene, 0 for no material
+    pub material_id: u32,
+    /// One plus the index of the top-level object in the scene, 0 for no object
+    pub object_id: u32,
+}
+
+impl AovSample {
+    pub fn miss() -> Self {
+        Self {
+            depth: f64::INFINITY,
+            normal: Vec3::default(),
+            albedo: Color::default(),
+            position: Point3::default(),
+            material_id: 0,
+            object_id: 0,
+        }
+    }
+    pub fn is_hit(&self) -> bool {
+        self.depth.is_finite()
+    }
+}
+
+/// Image-sized buffers of all AOVs, averaged over each pixel's samples
+///
+/// Depth, normal and position are averaged over the samples that hit a
+/// surface, albedo over all samples (misses count as black). The IDs are
+/// those of the first sample of the pixel.
+#[derive(Clone)]
+pub struct AovBuffers {
+    width: u32,
+    height: u32,
+    samples: Vec<u32>,
+    hits: Vec<u32>,
+    depth: Vec<f64>,
+    normal: Vec<Vec3>,
+    albedo: Vec<Color>,
+    position: Vec<Point3>,
+    material_id: Vec<u32>,
+    object_id: Vec<u32>,
+}
+
+impl AovBuffers {
+    pub fn new(width: u32, height: u32) -> Self {
+        let n = (width * height) as usize;
+        Self {
+            width,
+            height,
+            samples: vec![0; n],
+            hits: vec![0; n],
+            depth: vec![0.; n],
+            normal: vec![Vec3::default(); n],
+            albedo: vec![Color::default(); n],
+            position: vec![Point3::default(); n],
+            material_id: vec![0; n],
+            object_id: vec![0; n],
+        }
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    /// Adds a sample of the image pixel (x, y)
+    pub fn add_sample(&mut self, x: u32, y: u32, sample: &AovSample) {
+        let idx = self.index(x, y);
+        if self.samples[idx] == 0 {
+            self.material_id[idx] = sample.material_id;
+            self.object_id[idx] = sample.object_id;
+        }
+        self.samples[idx] += 1;
+        self.albedo[idx] += sample.albedo;
+        if sample.is_hit() {
+            self.hits[idx] += 1;
+            self.depth[idx] += sample.depth;
+            self.normal[idx] += sample.normal;
+            self.position[idx] += sample.position;
+        }
+    }
+
+    /// Adds the samples of pixel (ox, oy) of `other` to pixel (x, y)
+    pub fn add_pixel(&mut self, x: u32, y: u32, other: &AovBuffers, ox: u32, oy: u32) {
+        let (dst, src) = (self.index(x, y), other.index(ox, oy));
+        if self.samples[dst] == 0 {
+            self.material_id[dst] = other.material_id[src];
+            self.object_id[dst] = other.object_id[src];
+        }
+        self.samples[dst] += other.samples[src];
+        self.hits[dst] += other.hits[src];
+        self.depth[dst] += other.depth[src];
+        self.normal[dst] += other.normal[src];
+        self.albedo[dst] += other.albedo[src];
+        self.position[dst] += other.position[src];
+    }
+
+    /// Camera distance, infinite where no sample hit a surface
+    pub fn depth(&self, x: u32, y: u32) -> f64 {
+        let idx = self.index(x, y);
+        match self.hits[idx] {
+            0 => f64::INFINITY,
+            n => self.depth[idx] / n as f64,
+        }
+    }
+    pub fn normal(&self, x: u32, y: u32) -> Vec3 {
+        let idx = self.index(x, y);
+        self.normal[idx] / self.hits[idx].max(1) as f64
+    }
+    pub fn albedo(&self, x: u32, y: u32) -> Color {
+        let idx = self.index(x, y);
+        self.albedo[idx] / self.samples[idx].max(1) as f64
+    }
+    pub fn position(&self, x: u32, y: u32) -> Point3 {
+        let idx = self.index(x, y);
+        self.position[idx] / self.hits[idx].max(1) as f64
+    }
+    pub fn material_id(&self, x: u32, y: u32) -> u32 {
+        self.material_id[self.index(x, y)]
+    }
+    pub fn object_id(&self, x: u32, y: u32) -> u32 {
+        self.object_id[self.index(x, y)]
+    }
+
+    /// Value of an AOV as three channels; scalars are repeated in every channel
+    pub fn value(&self, kind: AovKind, x: u32, y: u32) -> Vec3 {
+        let scalar = |v: f64| Vec3::new(v, v, v);
+        match kind {
+            AovKind::Depth => scalar(self.depth(x, y)),
+            AovKind::Normal => self.normal(x, y),
+            AovKind::Albedo => self.albedo(x, y),
+            AovKind::Position => self.position(x, y),
+            AovKind::MaterialId => scalar(self.material_id(x, y) as f64),
+            AovKind::ObjectId => scalar(self.object_id(x, y) as f64),
+        }
+    }
+
+    /// 8-bit visualization of an AOV
+    ///
+    /// Depth is shown bright near the camera, normals and positions are
+    /// remapped to [0, 1] and IDs get a pseudo-random color each; these are
+    /// written without encoding. Albedo is gamma encoded like the beauty image.
+    pub fn to_image(&self, kind: AovKind) -> RgbImage {
+        let pixels = || (0..self.height).flat_map(move |y| (0..self.width).map(move |x| (x, y)));
+        let hits = |(x, y): &(u32, u32)| self.hits[self.index(*x, *y)] > 0;
+        let max_depth = pixels().filter(hits).map(|(x, y)| self.depth(x, y)).fold(0., f64::max);
+        let (lo, hi) = pixels().filter(hits).map(|(x, y)| self.position(x, y)).fold(
+            (Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY), Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY)),
+            |(lo, hi), p| {
+                (Vec3::new(lo.x().min(p.x()), lo.y().min(p.y()), lo.z().min(p.z())),
+                 Vec3::new(hi.x().max(p.x()), hi.y().max(p.y()), hi.z().max(p.z())))
+            });
+        let id_color = |id: u32| {
+            if id == 0 {
+                return Color::default();
+            }
+            let h = mix(u64::from(id));
+            let channel = |shift: u32| 0.2 + 0.8 * ((h >> shift) & 0xff) as f64 / 255.;
+            Color::new(channel(0), channel(8), channel(16))
+        };
+        let gamma = ToneMap::default();
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            let c = match kind {
+                AovKind::Depth => {
+                    let d = self.depth(x, y);
+                    if d.is_finite() && max_depth > 0. {
+                        heatmap_color(1. - d / max_depth)
+                    } else {
+                        Color::default()
+                    }
+                }
+                AovKind::Normal if hits(&(x, y)) => 0.5 * (self.normal(x, y) + Vec3::new(1., 1., 1.)),
+                AovKind::Albedo => return image::Rgb(gamma.to_rgb(self.albedo(x, y))),
+                AovKind::Position if hits(&(x, y)) => {
+                    let p = self.position(x, y);
+                    let extent = hi - lo;
+                    let unit = |v: f64, lo: f64, extent: f64| if extent > 0. { (v - lo) / extent } else { 0.5 };
+                    Color::new(unit(p.x(), lo.x(), extent.x()), unit(p.y(), lo.y(), extent.y()), unit(p.z(), lo.z(), extent.z()))
+                }
+                AovKind::MaterialId => id_color(self.material_id(x, y)),
+                AovKind::ObjectId => id_color(self.object_id(x, y)),
+                _ => Color::default(),
+            };
+            image::Rgb(c.to_rgb())
+        })
+    }
+
+    /// Writes an AOV as a Portable Float Map, keeping the raw values
+    pub fn write_pfm(&self, kind: AovKind, w: &mut dyn Write) -> io::Result<()> {
+        // Negative scale marks little-endian data; rows are stored bottom to top
+        write!(w, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let v = self.value(kind, x, y);
+                for c in &[v.x(), v.y(), v.z()] {
+                    w.write_all(&(*c as f32).to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save_pfm<P: AsRef<Path>>(&self, kind: AovKind, path: P) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_pfm(kind, &mut w)?;
+        w.flush()
+    }
+}
+
+impl FromStr for AovKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "depth" => Ok(AovKind::Depth),
+            "normal" => Ok(AovKind::Normal),
+            "albedo" => Ok(AovKind::Albedo),
+            "position" => Ok(AovKind::Position),
+            "material" | "material_id" => Ok(AovKind::MaterialId),
+            "object" | "object_id" => Ok(AovKind::ObjectId),
+            _ => Err(format!("unknown AOV '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for AovKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            AovKind::Depth => "depth",
+            AovKind::Normal => "normal",
+            AovKind::Albedo => "albedo",
+            AovKind::Position => "position",
+            AovKind::MaterialId => "material_id",
+            AovKind::ObjectId => "object_id",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_averaging() {
+        let mut aovs = AovBuffers::new(2, 1);
+        let hit = AovSample {
+            depth: 2.,
+            normal: Vec3::new(0., 1., 0.),
+            albedo: Color::new(0.5, 0.5, 0.5),
+            position: Point3::new(1., 2., 3.),
+            material_id: 3,
+            object_id: 7,
+        };
+        aovs.add_sample(0, 0, &hit);
+        aovs.add_sample(0, 0, &AovSample { depth: 4., ..hit });
+        aovs.add_sample(0, 0, &AovSample::miss());
+        assert_eq!(aovs.depth(0, 0), 3.);
+        assert_eq!(aovs.normal(0, 0), Vec3::new(0., 1., 0.));
+        assert!((aovs.albedo(0, 0).x() - 1. / 3.).abs() < 1e-12);
+        assert_eq!(aovs.object_id(0, 0), 7);
+        assert_eq!(aovs.material_id(0, 0), 3);
+
+        aovs.add_sample(1, 0, &AovSample::miss());
+        assert!(aovs.depth(1, 0).is_infinite());
+        assert_eq!(aovs.object_id(1, 0), 0);
+    }
+    #[test]
+    fn test_pfm_size() {
+        let aovs = AovBuffers::new(3, 2);
+        let mut out = Vec::new();
+        aovs.write_pfm(AovKind::Normal, &mut out).unwrap();
+        assert_eq!(out.len(), "PF\n3 2\n-1.0\n".len() + 3 * 2 * 3 * 4);
+    }
+}
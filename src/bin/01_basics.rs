@@ -17,6 +17,6 @@ fn main() {
             im.put_pixel(i, image_height - j - 1, pixel);
         }
     }
-    println!("");
+    println!();
     im.save("./01_output.png").unwrap();
 }
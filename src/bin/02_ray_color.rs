@@ -4,7 +4,7 @@ use raytracer::{Color, Point3, Ray, Vec3};
 fn ray_color(r: &Ray) -> Color {
     let unit_direction = r.direction.unit();
     let t = 0.5 * (unit_direction.y() + 1.0);
-    return (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0);
+    (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
 }
 fn main() {
     let aspect_ratio = 16. / 9.;
@@ -41,6 +41,6 @@ fn main() {
             im.put_pixel(i, image_height - j - 1, pixel);
         }
     }
-    println!("");
+    println!();
     im.save("./02_output.png").unwrap();
 }
@@ -10,11 +10,11 @@ fn hit_sphere(center: &Point3, radius: f64, r: &Ray) -> Option<f64> {
 
     // No intersection
     if discriminant <= 0. {
-        return None;
+        None
     } else {
         // This is distance to the sphere along the ray for near side point
         let position = (-half_b - discriminant.sqrt()) / (2.0 * a);
-        return Some(position);
+        Some(position)
     }
 }
 fn ray_color(r: &Ray) -> Color {
@@ -30,7 +30,7 @@ fn ray_color(r: &Ray) -> Color {
     let unit_direction = r.direction.unit();
     // Convert y-component (-1 to 1) to blue color
     let t = 0.5 * (unit_direction.y() + 1.0);
-    return (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0);
+    (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
 }
 fn main() {
     let aspect_ratio = 16. / 9.;
@@ -63,6 +63,6 @@ fn main() {
             im.put_pixel(i, image_height - j - 1, pixel);
         }
     }
-    println!("");
+    println!();
     im.save("./04_output.png").unwrap();
 }
@@ -2,14 +2,14 @@ use image::ImageBuffer;
 use raytracer::{color, point3, Color, Hittable, HittableList, Point3, Ray, SimpleSphere, Vec3};
 
 fn ray_color(r: &Ray, world: &HittableList) -> Color {
-    if let Some(rec) = world.hit(r, 0., std::f64::INFINITY) {
+    if let Some(rec) = world.hit(r, 0., f64::INFINITY) {
         // Assume rec.normal is unit vector
         return 0.5 * (rec.normal + color(1., 1., 1.));
     }
     let unit_direction = r.direction.unit();
     // Convert y-component (-1 to 1) to blue color
     let t = 0.5 * (unit_direction.y() + 1.0);
-    return (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0);
+    (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
 }
 fn main() {
     let aspect_ratio = 16. / 9.;
@@ -46,6 +46,6 @@ fn main() {
             im.put_pixel(i, image_height - j - 1, pixel);
         }
     }
-    println!("");
+    println!();
     im.save("./05_output.png").unwrap();
 }
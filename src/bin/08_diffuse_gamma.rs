@@ -1,28 +1,27 @@
 use image::ImageBuffer;
-use rand::prelude::*;
-use raytracer::{color, point3, Camera, Color, Hittable, HittableList, Ray, SimpleSphere,
+use raytracer::{IndependentSampler, Sampler, color, point3, Camera, Color, Hittable, HittableList, Ray, SimpleSphere,
                 random_unit_vector};
 
-fn ray_color(r: &Ray, world: &HittableList, depth: i32) -> Color {
+fn ray_color(r: &Ray, world: &HittableList, depth: i32, sampler: &mut dyn Sampler) -> Color {
     // If we've exceeded the ray bounce limit, no more light is gathered.
     if depth <= 0
     {
         return color(0., 0., 0.);
     }
-    if let Some(rec) = world.hit(r, 0.001, std::f64::INFINITY) 
+    if let Some(rec) = world.hit(r, 0.001, f64::INFINITY) 
     {
-        let target = rec.point + rec.normal + random_unit_vector();
+        let target = rec.point + rec.normal + random_unit_vector(sampler);
         let random_ray = Ray::new(rec.point, target - rec.point);
         // Cut intensity in half with every reflection
-        return 0.5 * ray_color(&random_ray, world, depth-1);
+        return 0.5 * ray_color(&random_ray, world, depth-1, sampler);
     }
     let unit_direction = r.direction.unit();
     // Convert y-component (-1 to 1) to blue color
     let t = 0.5 * (unit_direction.y() + 1.0);
-    return (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0);
+    (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
 }
 fn main() {
-    let mut rng = thread_rng();
+    let mut sampler = IndependentSampler::new(0);
 
     let aspect_ratio = 16. / 9.;
     let image_width = 384;
@@ -42,15 +41,15 @@ fn main() {
         for i in 0..image_width {
             let mut pixel_color = color(0., 0., 0.);
             for _ in 0..samples_per_pixel {
-                let u = ((i as f64) + rng.gen::<f64>()) / (image_width as f64 - 1.0);
-                let v = ((j as f64) + rng.gen::<f64>()) / (image_height as f64 - 1.0);
-                let r = camera.get_ray(u, v);
-                pixel_color += ray_color(&r, &world, max_depth);
+                let u = ((i as f64) + sampler.get_1d()) / (image_width as f64 - 1.0);
+                let v = ((j as f64) + sampler.get_1d()) / (image_height as f64 - 1.0);
+                let r = camera.get_ray(u, v, &mut sampler);
+                pixel_color += ray_color(&r, &world, max_depth, &mut sampler);
             }
             let pixel = image::Rgb(pixel_color.to_rgb_scaled_gamma2(samples_per_pixel));
             im.put_pixel(i, image_height - j - 1, pixel);
         }
     }
-    println!("");
+    println!();
     im.save("./08_output.png").unwrap();
 }
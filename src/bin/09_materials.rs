@@ -1,30 +1,29 @@
 use std::sync::Arc;
 use image::ImageBuffer;
-use rand::prelude::*;
-use raytracer::{color, point3, Camera, Color, Hittable, HittableList, Ray, Sphere};
+use raytracer::{IndependentSampler, Sampler, color, point3, Camera, Color, Hittable, HittableList, Ray, Sphere};
 use raytracer::materials::{Lambertian, Metal};
 
-fn ray_color(r: &Ray, world: &HittableList, depth: i32) -> Color {
+fn ray_color(r: &Ray, world: &HittableList, depth: i32, sampler: &mut dyn Sampler) -> Color {
     // If we've exceeded the ray bounce limit, no more light is gathered.
     if depth <= 0
     {
         return color(0., 0., 0.);
     }
-    if let Some(rec) = world.hit(r, 0.001, std::f64::INFINITY) 
+    if let Some(rec) = world.hit(r, 0.001, f64::INFINITY) 
     {
-        if let Some((scattered_ray, attenuation)) = rec.scatter(r)
+        if let Some((scattered_ray, attenuation)) = rec.scatter(r, sampler)
         {
-            return attenuation * ray_color(&scattered_ray, world, depth -1);
+            return attenuation * ray_color(&scattered_ray, world, depth -1, sampler);
         }
         return color(0., 0., 0.);
     }
     let unit_direction = r.direction.unit();
     // Convert y-component (-1 to 1) to blue color
     let t = 0.5 * (unit_direction.y() + 1.0);
-    return (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0);
+    (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
 }
 fn main() {
-    let mut rng = thread_rng();
+    let mut sampler = IndependentSampler::new(0);
 
     let aspect_ratio = 16. / 9.;
     let image_width = 384;
@@ -54,15 +53,15 @@ fn main() {
         for i in 0..image_width {
             let mut pixel_color = color(0., 0., 0.);
             for _ in 0..samples_per_pixel {
-                let u = ((i as f64) + rng.gen::<f64>()) / (image_width as f64 - 1.0);
-                let v = ((j as f64) + rng.gen::<f64>()) / (image_height as f64 - 1.0);
-                let r = camera.get_ray(u, v);
-                pixel_color += ray_color(&r, &world, max_depth);
+                let u = ((i as f64) + sampler.get_1d()) / (image_width as f64 - 1.0);
+                let v = ((j as f64) + sampler.get_1d()) / (image_height as f64 - 1.0);
+                let r = camera.get_ray(u, v, &mut sampler);
+                pixel_color += ray_color(&r, &world, max_depth, &mut sampler);
             }
             let pixel = image::Rgb(pixel_color.to_rgb_scaled_gamma2(samples_per_pixel));
             im.put_pixel(i, image_height - j - 1, pixel);
         }
     }
-    println!("");
+    println!();
     im.save("./09_output.png").unwrap();
 }
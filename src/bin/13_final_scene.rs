@@ -1,11 +1,21 @@
 use std::sync::Arc;
 use rand::prelude::*;
-use raytracer::{color, point3, Vec3, Camera, Color, HittableList, Sphere};
-use raytracer::materials::{Material, Lambertian, Metal, Dielectric};
-use raytracer::renderers::{Renderer, SimpleRenderer, RayonRenderer};
+use rand::rngs::StdRng;
+use raytracer::{color, point3, Vec3, Camera, Color, HittableList, Medium, Sphere};
+use raytracer::{ToneMap, ToneMapOperator, TransferFunction, Filter, FilterKind, SamplerKind, AdaptiveSampling, TileOrder, Checkpoint};
+use raytracer::{RenderControl, TerminalProgress, TimeBudget, AovKind, Denoiser};
+use raytracer::{CameraModel, Projection, OrthographicCamera, FisheyeCamera, EquirectangularCamera};
+use raytracer::{Eye, OdsCamera, StereoCamera, StereoLayout, StereoRig};
+use raytracer::{Aperture, ApertureMask, LensSystem, LensSystemCamera, load_lights, PhysicalSky};
+use raytracer::{AnimatedScene, CameraTrack, FrameRange, Interpolation, Track, TransformTrack};
+use raytracer::materials::{Material, Lambertian, Metal, Dielectric, Subsurface};
+use raytracer::renderers::{Renderer, RenderSettings, Integrator, BounceLimits, RussianRoulette, SimpleRenderer, RayonRenderer, TileRenderer, ProgressiveRenderer, BdptRenderer, PhotonMapRenderer};
 
-fn random_scene() -> HittableList {
-    let mut rng = thread_rng();
+/// `glass` fills the glass spheres, which are otherwise clear, and the large
+/// brown sphere scatters light below its surface if given a `subsurface` mean
+/// free path. Also returns the index of the large glass sphere.
+fn random_scene(seed: u64, glass: Option<Medium>, subsurface: Option<f64>) -> (HittableList, usize) {
+    let mut rng = StdRng::seed_from_u64(seed);
     let mut world = HittableList::new();
     
     let ground_material = Arc::new(Lambertian::new(color(0.5, 0.5, 0.5)));
@@ -22,16 +32,16 @@ fn random_scene() -> HittableList {
             if (center - point3(4., 0.2, 0.)).length() > 0.9 {
                 let sphere_material : Arc<dyn Material + Sync + Send> = if choose_mat < 0.8 {
                     // diffuse
-                    let albedo = Color::random() * Color::random();
+                    let albedo = Color::random(&mut rng) * Color::random(&mut rng);
                     Arc::new(Lambertian::new(albedo))
                 } else if choose_mat < 0.95 {
                     // metal
-                    let albedo = Color::random_range(0.5, 1.);
+                    let albedo = Color::random_range(&mut rng, 0.5, 1.);
                     let fuzz = rng.gen_range(0., 0.5);
                     Arc::new(Metal::new(albedo, fuzz))
                 } else {
                     // glass
-                    Arc::new(Dielectric::new(1.5))
+                    Arc::new(glass_material(glass))
                 };
                 world.add(Box::new(Sphere::new(center, 0.2, sphere_material)));
             }
@@ -39,16 +49,39 @@ fn random_scene() -> HittableList {
         }
     }
 
-    let material_1 = Arc::new(Dielectric::new(1.5));
+    let material_1 = Arc::new(glass_material(glass));
+    let glass_sphere = world.len();
     world.add(Box::new(Sphere::new(point3(0., 1., 0.), 1.0, material_1)));
 
-    let material_2 = Arc::new(Lambertian::new(color(0.4, 0.2, 0.1)));
+    let material_2: Arc<dyn Material + Sync + Send> = match subsurface {
+        Some(d) => Arc::new(Subsurface::new(color(0.4, 0.2, 0.1), color(d, d, d))),
+        None => Arc::new(Lambertian::new(color(0.4, 0.2, 0.1))),
+    };
     world.add(Box::new(Sphere::new(point3(-4., 1., 0.), 1.0, material_2)));
 
     let material_3 = Arc::new(Metal::new(color(0.7, 0.6, 0.5), 0.0));
     world.add(Box::new(Sphere::new(point3(4., 1., 0.), 1.0, material_3)));
     
-    world
+    (world, glass_sphere)
+}
+
+fn glass_material(medium: Option<Medium>) -> Dielectric {
+    match medium {
+        Some(medium) => Dielectric::new(1.5).with_medium(medium),
+        None => Dielectric::new(1.5),
+    }
+}
+
+/// Parses a color given as "r,g,b"
+fn parse_color(s: &str) -> Result<Color, String> {
+    let values: Vec<f64> = s
+        .split(',')
+        .map(|v| v.trim().parse::<f64>().map_err(|e| format!("invalid color '{}': {}", s, e)))
+        .collect::<Result<_, _>>()?;
+    match values[..] {
+        [r, g, b] => Ok(color(r, g, b)),
+        _ => Err(format!("expected a color as r,g,b, got '{}'", s)),
+    }
 }
 
 use structopt::StructOpt;
@@ -59,52 +92,535 @@ struct Opt {
     #[structopt(short = "p", long)]
     parallel: bool,
 
+    /// Use tile-based parallel renderer
+    #[structopt(short = "t", long)]
+    tiled: bool,
+
+    /// Tile size in pixels for the tiled renderer
+    #[structopt(long = "tile-size", default_value = "32")]
+    tile_size: u32,
+
+    /// Tile order for the tiled renderer (scanline, spiral, hilbert)
+    #[structopt(long = "tile-order", default_value = "scanline")]
+    tile_order: TileOrder,
+
+    /// Quantity rendered (path, whitted, ao, normals, facing, uv, bounces);
+    /// whitted is a noise-free preview, the others diagnostic views
+    #[structopt(long, default_value = "path")]
+    integrator: Integrator,
+
+    /// Distance within which ambient occlusion counts blockers, in scene units
+    #[structopt(long = "ao-radius", default_value = "1")]
+    ao_radius: f64,
+
+    /// Checker squares along each surface coordinate of the uv integrator
+    #[structopt(long = "uv-checks", default_value = "16")]
+    uv_checks: f64,
+
+    /// Use bidirectional path tracer, connecting camera and light paths
+    #[structopt(long)]
+    bdpt: bool,
+
+    /// Use progressive photon mapping, for caustics from point and spot lights
+    #[structopt(long = "photon-mapping")]
+    photon_mapping: bool,
+
+    /// Photons traced per sample per pixel by the photon mapper
+    #[structopt(long, default_value = "100000")]
+    photons: u32,
+
+    /// Initial photon gathering radius of the photon mapper, in scene units
+    #[structopt(long = "photon-radius", default_value = "0.1")]
+    photon_radius: f64,
+
+    /// Use progressive renderer, accumulating passes and writing checkpoints
+    #[structopt(long)]
+    progressive: bool,
+
+    /// Samples per pixel added by each progressive pass
+    #[structopt(long = "samples-per-pass", default_value = "4")]
+    samples_per_pass: u32,
+
+    /// Checkpoint file for the progressive renderer
+    #[structopt(long = "checkpoint")]
+    checkpoint: Option<String>,
+
+    /// Minimum time between checkpoints, in seconds
+    #[structopt(long = "checkpoint-interval", default_value = "30")]
+    checkpoint_interval: u64,
+
+    /// Resume the progressive render from the checkpoint file, if it exists
+    #[structopt(long, requires = "checkpoint")]
+    resume: bool,
+
     /// Set image width
     #[structopt(short = "w", long = "image-width", default_value = "384")]
     image_width: u32,
 
+    /// Camera projection (perspective, orthographic, fisheye, equirectangular)
+    #[structopt(long = "projection", default_value = "perspective")]
+    projection: Projection,
+
+    /// Field of view in degrees; vertical for perspective, of the image circle for fisheye
+    #[structopt(long = "fov")]
+    fov: Option<f64>,
+
+    /// Number of diaphragm blades giving a polygonal aperture (0 for circular)
+    #[structopt(long = "aperture-blades", default_value = "0")]
+    aperture_blades: u32,
+
+    /// Grayscale image giving the aperture shape
+    #[structopt(long = "aperture-mask")]
+    aperture_mask: Option<String>,
+
+    /// Strength of cat-eye vignetting of the thin lens camera
+    #[structopt(long = "cat-eye", default_value = "0")]
+    cat_eye: f64,
+
+    /// Render through a lens table file (radius, thickness, ior, aperture
+    /// per line, in mm), or "double-gauss" for the built-in 50mm lens
+    #[structopt(long = "lens")]
+    lens: Option<String>,
+
+    /// Scale of the lens system's aperture stop, below 1 to stop down
+    #[structopt(long = "stop-scale", default_value = "1")]
+    stop_scale: f64,
+
+    /// Render both eyes for VR (side-by-side, over-under); panoramas become
+    /// omni-directional stereo with the equirectangular projection
+    #[structopt(long = "stereo", conflicts_with = "frames")]
+    stereo: Option<StereoLayout>,
+
+    /// Distance between the eyes of the stereo rig, in scene units
+    #[structopt(long = "ipd", default_value = "0.064")]
+    ipd: f64,
+
+    /// Distance at which the stereo eyes converge, defaults to the focus distance
+    #[structopt(long = "convergence")]
+    convergence: Option<f64>,
+
+    /// Add the point, spot and directional lights listed in a file
+    #[structopt(long = "lights")]
+    lights: Option<std::path::PathBuf>,
+
+    /// Absorption per unit distance of the inside of the glass spheres, as r,g,b
+    #[structopt(long = "glass-absorption", parse(try_from_str = parse_color))]
+    glass_absorption: Option<Color>,
+
+    /// Scattering per unit distance of the inside of the glass spheres, as r,g,b
+    #[structopt(long = "glass-scattering", parse(try_from_str = parse_color))]
+    glass_scattering: Option<Color>,
+
+    /// Make the large brown sphere translucent, with this mean free path below its surface
+    #[structopt(long = "subsurface")]
+    subsurface: Option<f64>,
+
+    /// Light the scene with a physical sky and sun at this many degrees above the horizon
+    #[structopt(long = "sun-elevation")]
+    sun_elevation: Option<f64>,
+
+    /// Direction of the sun in degrees clockwise from -z towards +x
+    #[structopt(long = "sun-azimuth", default_value = "60")]
+    sun_azimuth: f64,
+
+    /// Haziness of the physical sky, from 2 (clear) to 10
+    #[structopt(long = "turbidity", default_value = "3")]
+    turbidity: f64,
+
+    /// Scale from sky luminance in kcd/m² to scene radiance
+    #[structopt(long = "sky-intensity", default_value = "0.05")]
+    sky_intensity: f64,
+
+    /// Maximum number of bounces of a path
+    #[structopt(long = "max-depth", default_value = "50")]
+    max_depth: i32,
+
+    /// Maximum number of diffuse bounces of a path
+    #[structopt(long = "max-diffuse")]
+    max_diffuse: Option<u32>,
+
+    /// Maximum number of specular reflections of a path
+    #[structopt(long = "max-specular")]
+    max_specular: Option<u32>,
+
+    /// Maximum number of refractions of a path
+    #[structopt(long = "max-transmission")]
+    max_transmission: Option<u32>,
+
+    /// Maximum number of scattering events of a path inside media
+    #[structopt(long = "max-volume", default_value = "256")]
+    max_volume: u32,
+
+    /// Randomly terminate dim paths after this many bounces
+    #[structopt(long = "russian-roulette")]
+    russian_roulette: Option<u32>,
+
     /// Set samples per pixel
     #[structopt(short = "s", long = "samples", default_value = "100")]
     samples: u32,
+
+    /// Tone mapping operator (clamp, reinhard, filmic, aces)
+    #[structopt(long = "tonemap", default_value = "clamp")]
+    tone_map: ToneMapOperator,
+
+    /// Exposure adjustment in stops
+    #[structopt(long = "exposure", default_value = "0", allow_hyphen_values = true)]
+    exposure: f64,
+
+    /// Output transfer function (linear, gamma2, srgb)
+    #[structopt(long = "transfer", default_value = "gamma2")]
+    transfer: TransferFunction,
+
+    /// Pixel reconstruction filter (box, tent, gaussian, mitchell, lanczos)
+    #[structopt(long = "filter", default_value = "box")]
+    filter: FilterKind,
+
+    /// Filter radius in pixels
+    #[structopt(long = "filter-radius", default_value = "0.5")]
+    filter_radius: f64,
+
+    /// Seed for scene generation and sampling
+    #[structopt(long = "seed", default_value = "0")]
+    seed: u64,
+
+    /// Sampler (independent, stratified, halton, sobol)
+    #[structopt(long = "sampler", default_value = "independent")]
+    sampler: SamplerKind,
+
+    /// Enable adaptive sampling, stopping pixels whose relative error is below this threshold
+    #[structopt(long = "adaptive-threshold")]
+    adaptive_threshold: Option<f64>,
+
+    /// Samples taken between adaptive convergence checks
+    #[structopt(long = "min-samples", default_value = "16")]
+    min_samples: u32,
+
+    /// Stop rendering after this many seconds of wall-clock time
+    #[structopt(long = "time-budget")]
+    time_budget: Option<f64>,
+
+    /// Stop rendering after this many seconds spent tracing samples, summed over threads
+    #[structopt(long = "sample-time-budget", conflicts_with = "time-budget")]
+    sample_time_budget: Option<f64>,
+
+    /// Write a per-pixel sample count heatmap to this file
+    #[structopt(long = "heatmap")]
+    heatmap: Option<String>,
+
+    /// Write the first-hit AOVs (depth, normal, albedo, position, material and
+    /// object IDs) to <prefix>_<aov>.pfm, with a .png preview of each
+    #[structopt(long = "aovs")]
+    aovs: Option<String>,
+
+    /// Render a turntable animation for this range of frames (e.g. 1-96) to
+    /// frame_0001.png etc. instead of a single image, with the perspective
+    /// camera
+    #[structopt(long = "frames", conflicts_with_all = &["checkpoint", "projection", "lens", "aovs", "heatmap"])]
+    frames: Option<FrameRange>,
+
+    /// Frames per second of the animation; one turn takes 4 seconds
+    #[structopt(long = "fps", default_value = "24")]
+    fps: f64,
+
+    /// Keyframe interpolation of the animation (linear, catmull-rom)
+    #[structopt(long = "interpolation", default_value = "catmull-rom")]
+    interpolation: Interpolation,
+
+    /// Directory for the animation frames
+    #[structopt(long = "output-dir", default_value = ".")]
+    output_dir: String,
+
+    /// Denoise the image using albedo, normal and depth buffers
+    #[structopt(long)]
+    denoise: bool,
+
+    /// Number of a-trous wavelet iterations used by the denoiser
+    #[structopt(long = "denoise-iterations", default_value = "5")]
+    denoise_iterations: u32,
 }
 
 fn main() {
     let opt = Opt::from_args();
 
     // Define world
-    let world = random_scene();
+    let glass = match (opt.glass_absorption, opt.glass_scattering) {
+        (None, None) => None,
+        (absorption, scattering) => Some(Medium::new(absorption.unwrap_or_default(), scattering.unwrap_or_default())),
+    };
+    let (mut world, glass_sphere) = random_scene(opt.seed, glass, opt.subsurface);
+    if let Some(path) = &opt.lights {
+        for light in load_lights(path).unwrap_or_else(|e| {
+            eprintln!("Invalid light setup {}", e);
+            std::process::exit(1);
+        }) {
+            world.add_light(light);
+        }
+    }
+    if let Some(elevation) = opt.sun_elevation {
+        let sky = PhysicalSky::new(elevation, opt.sun_azimuth, opt.turbidity).with_intensity(opt.sky_intensity);
+        world.add_light(Arc::new(sky.sun_light()));
+        world.set_environment(Arc::new(sky));
+    }
 
     // Render
-    let aspect_ratio = 16. / 9.;
-    let image_width = opt.image_width;
-    let image_height = (image_width as f32 / aspect_ratio as f32) as u32;
+    // Panoramas cover 360 by 180 degrees
+    let aspect_ratio = if opt.projection == Projection::Equirectangular { 2. } else { 16. / 9. };
+    let mut image_width = opt.image_width;
+    let mut image_height = (image_width as f32 / aspect_ratio as f32) as u32;
     let samples_per_pixel = opt.samples as i32;
-    let max_depth = 50;
+    let max_depth = opt.max_depth;
 
     let lookfrom = point3(13., 2., 3.);
     let lookat = point3(0., 0., 0.);
     let vup = Vec3::new(0., 1., 0.);
     let dist_to_focus = 10.0;
     let aperture = 0.1;
-    let vfov_deg = 20.;
+    let vfov_deg = opt.fov.unwrap_or(20.);
 
-    let camera = Camera::new_with_depth_of_field(lookfrom, 
+    let aperture_shape = match &opt.aperture_mask {
+        Some(path) => {
+            let mask = image::open(path)
+                .map_err(|e| e.to_string())
+                .and_then(|image| ApertureMask::from_image(&image.to_luma()))
+                .unwrap_or_else(|e| {
+                    eprintln!("Invalid aperture mask {}: {}", path, e);
+                    std::process::exit(1);
+                });
+            Aperture::Mask(Arc::new(mask))
+        }
+        None if opt.aperture_blades > 0 => Aperture::polygon(opt.aperture_blades),
+        None => Aperture::Circular,
+    };
+
+    let camera: Box<dyn CameraModel> = match opt.projection {
+        _ if opt.lens.is_some() => {
+            let lens = match opt.lens.as_deref() {
+                Some("double-gauss") => LensSystem::double_gauss_50mm(),
+                Some(path) => {
+                    let table = std::fs::read_to_string(path).expect("Failed to read lens table");
+                    LensSystem::parse(&table).unwrap_or_else(|e| {
+                        eprintln!("Invalid lens table {}: {}", path, e);
+                        std::process::exit(1);
+                    })
+                }
+                None => unreachable!(),
+            };
+            // Scene units are meters; 35mm film diagonal
+            let camera = LensSystemCamera::new(lookfrom, lookat, vup, lens, 43.3, aspect_ratio, dist_to_focus, 1e-3)
+                .unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                });
+            Box::new(camera.with_aperture(aperture_shape.clone()).with_stop_scale(opt.stop_scale))
+        }
+        Projection::Perspective => Box::new(Camera::new_with_depth_of_field(lookfrom, 
                                                  lookat, 
                                                  vup, 
                                                  vfov_deg, 
                                                  aspect_ratio, 
                                                  aperture, 
-                                                 dist_to_focus);
+                                                 dist_to_focus)
+                                                 .with_aperture(aperture_shape.clone())
+                                                 .with_cat_eye(opt.cat_eye)),
+        // Same framing as the perspective camera at the focus distance
+        Projection::Orthographic => {
+            let viewport_height = 2. * dist_to_focus * (vfov_deg.to_radians() / 2.).tan();
+            Box::new(OrthographicCamera::new(lookfrom, lookat, vup, viewport_height, aspect_ratio))
+        }
+        Projection::Fisheye => Box::new(FisheyeCamera::new(lookfrom, lookat, vup, opt.fov.unwrap_or(180.), aspect_ratio)),
+        Projection::Equirectangular => Box::new(EquirectangularCamera::new(lookfrom, lookat, vup)),
+    };
+
+    // Each eye gets an image of the requested size
+    let camera: Box<dyn CameraModel> = match opt.stereo {
+        Some(layout) => {
+            let convergence = opt.convergence.unwrap_or(dist_to_focus);
+            if opt.lens.is_some() {
+                eprintln!("Stereo rendering does not support lens systems");
+                std::process::exit(1);
+            }
+            let stereo = match opt.projection {
+                Projection::Perspective => {
+                    let builder = Camera::builder()
+                        .position(lookfrom)
+                        .target(lookat)
+                        .up(vup)
+                        .vfov(vfov_deg)
+                        .aspect_ratio(aspect_ratio)
+                        .aperture(aperture)
+                        .focus_distance(dist_to_focus);
+                    let rig = StereoRig::new(builder, opt.ipd, convergence);
+                    let eye = |eye| {
+                        rig.eye(eye).map(|camera| camera.with_aperture(aperture_shape.clone()).with_cat_eye(opt.cat_eye))
+                    };
+                    Some(eye(Eye::Left).and_then(|left| Ok(StereoCamera::new(Box::new(left), Box::new(eye(Eye::Right)?), layout))))
+                }
+                Projection::Equirectangular => {
+                    let eye = |eye| Box::new(OdsCamera::new(lookfrom, lookat, vup, eye, opt.ipd).with_convergence(convergence));
+                    Some(Ok(StereoCamera::new(eye(Eye::Left), eye(Eye::Right), layout)))
+                }
+                _ => None,
+            };
+            let stereo = match stereo {
+                Some(Ok(stereo)) => stereo,
+                Some(Err(e)) => {
+                    eprintln!("Invalid stereo rig: {}", e);
+                    std::process::exit(1);
+                }
+                None => {
+                    eprintln!("Stereo rendering needs the perspective or equirectangular projection");
+                    std::process::exit(1);
+                }
+            };
+            let (width, height) = stereo.image_size(image_width, image_height);
+            image_width = width;
+            image_height = height;
+            Box::new(stereo)
+        }
+        None => camera,
+    };
 
-    let im = if !opt.parallel
+    let mut control = RenderControl::default().with_observer(Arc::new(TerminalProgress::default()));
+    if let Some(secs) = opt.time_budget {
+        control = control.with_budget(TimeBudget::WallClock(std::time::Duration::from_secs_f64(secs)));
+    }
+    if let Some(secs) = opt.sample_time_budget {
+        control = control.with_budget(TimeBudget::SampleTime(std::time::Duration::from_secs_f64(secs)));
+    }
+
+    let settings = RenderSettings {
+        tone_map: ToneMap::new(opt.tone_map, opt.exposure, opt.transfer),
+        filter: Filter::new(opt.filter, opt.filter_radius),
+        seed: opt.seed,
+        sampler: opt.sampler,
+        adaptive: opt.adaptive_threshold.map(|t| AdaptiveSampling::new(opt.min_samples, t)),
+        control,
+        denoiser: if opt.denoise { Some(Denoiser::new(opt.denoise_iterations)) } else { None },
+        aovs: opt.aovs.is_some(),
+        bounce_limits: BounceLimits {
+            diffuse: opt.max_diffuse.unwrap_or(u32::MAX),
+            specular: opt.max_specular.unwrap_or(u32::MAX),
+            transmission: opt.max_transmission.unwrap_or(u32::MAX),
+            volume: opt.max_volume,
+        },
+        russian_roulette: opt.russian_roulette.map(|min_bounces| RussianRoulette { min_bounces, ..RussianRoulette::default() }),
+        integrator: match opt.integrator {
+            Integrator::AmbientOcclusion { .. } => Integrator::AmbientOcclusion { radius: opt.ao_radius },
+            Integrator::UvChecker { .. } => Integrator::UvChecker { checks: opt.uv_checks },
+            integrator => integrator,
+        },
+    };
+
+    let renderer: Box<dyn Renderer> = if opt.progressive
     {
-        let renderer = SimpleRenderer::default();
-        renderer.render(world, &camera, image_width, image_height, samples_per_pixel, max_depth)
+        let mut renderer = ProgressiveRenderer::new(settings)
+            .with_samples_per_pass(opt.samples_per_pass);
+        if let Some(path) = &opt.checkpoint {
+            let interval = std::time::Duration::from_secs(opt.checkpoint_interval);
+            renderer = renderer.with_checkpoint(path, interval);
+            if opt.resume && std::path::Path::new(path).exists() {
+                renderer = Checkpoint::load(path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|checkpoint| renderer.resume(checkpoint, image_width, image_height))
+                    .unwrap_or_else(|e| {
+                        eprintln!("Cannot resume from checkpoint {}: {}", path, e);
+                        std::process::exit(1);
+                    });
+            }else if opt.resume {
+                eprintln!("Warning: checkpoint {} does not exist, starting a new render", path);
+            }
+        }
+        Box::new(renderer)
+    }else if opt.bdpt
+    {
+        Box::new(BdptRenderer::new(settings))
+    }else if opt.photon_mapping
+    {
+        Box::new(PhotonMapRenderer::new(settings)
+                 .with_photons_per_pass(opt.photons)
+                 .with_initial_radius(opt.photon_radius))
+    }else if opt.tiled
+    {
+        Box::new(TileRenderer::new(settings)
+                 .with_tile_size(opt.tile_size)
+                 .with_tile_order(opt.tile_order))
+    }else if opt.parallel
+    {
+        Box::new(RayonRenderer::new(settings))
     }else{
-        let renderer = RayonRenderer::default();
-        renderer.render(world, &camera, image_width, image_height, samples_per_pixel, max_depth)
+        Box::new(SimpleRenderer::new(settings))
     };
+    if let Some(frames) = opt.frames {
+        let base_camera = Camera::builder()
+            .up(vup)
+            .aspect_ratio(aspect_ratio)
+            .aperture(aperture)
+            .focus_distance(dist_to_focus);
+        let (mut camera_track, animated) = turntable(world, glass_sphere, opt.interpolation);
+        if let Some(fov) = opt.fov {
+            camera_track.vfov = Track::constant(fov);
+        }
+        for frame in frames.frames() {
+            let time = (frame as f64 / opt.fps) % TURNTABLE_PERIOD;
+            let camera = camera_track.builder_at(time, base_camera.clone()).build()
+                .expect("invalid animated camera")
+                .with_aperture(aperture_shape.clone())
+                .with_cat_eye(opt.cat_eye);
+            let image = renderer.render(animated.scene_at(time), &camera, image_width, image_height, samples_per_pixel, max_depth);
+            let path = std::path::Path::new(&opt.output_dir).join(format!("frame_{:04}.png", frame));
+            image.save(&path).unwrap();
+            println!("Wrote {}", path.display());
+        }
+        return;
+    }
+
+    let mut film = renderer.render_film(world, camera.as_ref(), image_width, image_height, samples_per_pixel, max_depth);
+    if let (Some(prefix), Some(aovs)) = (&opt.aovs, film.aovs()) {
+        for &kind in AovKind::ALL.iter() {
+            aovs.save_pfm(kind, format!("{}_{}.pfm", prefix, kind)).unwrap();
+            aovs.to_image(kind).save(format!("{}_{}.png", prefix, kind)).unwrap();
+        }
+    }
+    if let (Some(denoiser), Some(aovs)) = (&renderer.settings().denoiser, film.aovs()) {
+        film = denoiser.denoise(&film, aovs);
+    }
     
-    println!("");
-    im.save("./13_output.png").unwrap();
+    println!();
+    film.to_rgb_image(&renderer.settings().tone_map).save("./13_output.png").unwrap();
+    if let Some(path) = opt.heatmap {
+        film.sample_count_image().save(path).unwrap();
+    }
+}
+
+/// Length of the turntable animation in seconds, after which it loops
+const TURNTABLE_PERIOD: f64 = 4.;
+
+/// Camera orbiting the scene once every 4 seconds while the glass sphere
+/// `glass` bounces
+fn turntable(world: HittableList, glass: usize, interpolation: Interpolation) -> (CameraTrack, AnimatedScene) {
+    let keys = 8;
+    let mut position = Track::new(interpolation);
+    for k in 0..=keys {
+        let angle = 2. * std::f64::consts::PI * k as f64 / keys as f64;
+        // Starts at the still image's viewpoint (13, 2, 3)
+        let start = 3f64.atan2(13.);
+        let radius = (13f64 * 13. + 3. * 3.).sqrt();
+        position.add_key(TURNTABLE_PERIOD * k as f64 / keys as f64,
+                         point3(radius * (start + angle).cos(), 2., radius * (start + angle).sin()));
+    }
+    let camera_track = CameraTrack {
+        position,
+        target: Track::constant(point3(0., 0., 0.)),
+        vfov: Track::new(interpolation).with_key(0., 20.).with_key(2., 25.).with_key(4., 20.),
+    };
+
+    let mut translation = Track::new(interpolation);
+    for k in 0..=8 {
+        let height = if k % 2 == 0 { 0. } else { 1. };
+        translation.add_key(0.5 * k as f64, Vec3::new(0., height, 0.));
+    }
+    let mut animated = AnimatedScene::from_list(world);
+    animated.animate(glass, TransformTrack { translation, ..TransformTrack::default() })
+        .expect("invalid glass sphere track");
+    (camera_track, animated)
 }
@@ -1,7 +1,64 @@
-use crate::{Point3, Ray, Vec3};
-use crate::utils::random_in_unit_disk;
+use crate::{Aperture, Point3, Ray, Sampler, Vec3, LENS_DIMENSION};
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+/// Maps normalized image coordinates to camera rays
+///
+/// `s` runs from 0 at the left edge of the image to 1 at the right edge and
+/// `t` from 0 at the bottom to 1 at the top.
+pub trait CameraModel: Send + Sync {
+    fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Ray;
+
+    /// Like `get_ray`, but returns `None` for rays blocked inside the camera,
+    /// which is how vignetting reaches the image
+    fn try_get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        Some(self.get_ray(s, t, sampler))
+    }
+
+    /// Ray through the film position (x, y), in pixels from the bottom left
+    /// corner of a `width` x `height` image. By default the centers of the
+    /// outermost pixels map to the image edges.
+    fn film_ray(&self, x: f64, y: f64, width: u32, height: u32, sampler: &mut dyn Sampler) -> Option<Ray> {
+        self.try_get_ray(x / (f64::from(width) - 1.), y / (f64::from(height) - 1.), sampler)
+    }
+
+    /// Connects a point in the scene to the lens, for tracing light paths
+    /// from the lights to the camera. `None` if the point is behind the
+    /// camera or the camera does not support it.
+    fn sample_importance(&self, _point: Point3, _sampler: &mut dyn Sampler) -> Option<ImportanceSample> {
+        None
+    }
+
+    /// Density per unit solid angle of the directions of the rays generated
+    /// for uniformly spread image positions, or 0 without `sample_importance`
+    fn direction_pdf(&self, _direction: Vec3) -> f64 {
+        0.
+    }
+}
+
+/// Point on the lens seeing a point in the scene
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImportanceSample {
+    /// Image coordinates the point is seen at, outside [0, 1] if it is out of view
+    pub s: f64,
+    pub t: f64,
+    pub lens_point: Point3,
+    /// Camera importance divided by the density of choosing the lens point,
+    /// per unit solid angle as seen from the scene point
+    pub weight: f64,
+}
 
 pub struct Camera {
+    look_from: Point3,
+    look_at: Point3,
+    vup: Vec3,
+    vfov_deg: f64,
+    aspect_ratio: f64,
+    aperture: f64,
+    focus_distance: f64,
+    shutter_open: f64,
+    shutter_close: f64,
     origin: Point3,
     u: Vec3,
     v: Vec3, 
@@ -10,63 +67,41 @@ pub struct Camera {
     horizontal: Vec3,
     vertical: Vec3,
     lens_radius: f64,
+    aperture_shape: Aperture,
+    /// Offset of the lens barrel opening per unit distance from the image
+    /// center, in lens radii; 0 disables cat-eye vignetting
+    cat_eye: f64,
 }
 
-impl Camera {
-    pub fn default() -> Self {
-        let aspect_ratio = 16. / 9.;
-        let viewport_height = 2.0;
-        let viewport_width = aspect_ratio * viewport_height;
-        let focal_length = 1.0;
-
-        let origin = Point3::new(0., 0., 0.);
-        let horizontal = Vec3::new(viewport_width, 0., 0.);
-        let vertical = Vec3::new(0., viewport_height, 0.);
-        let lower_left_corner =
-            origin - horizontal / 2. - vertical / 2. - Vec3::new(0., 0., focal_length);
-
-        Self {
-            origin,
-            lower_left_corner,
-            horizontal,
-            vertical,
-            u: Vec3::default(),
-            v: Vec3::default(),
-            w: Vec3::default(),
-            lens_radius: 0.,
-        }
+/// Camera with the `CameraBuilder` defaults, at the origin looking down -z
+impl Default for Camera {
+    fn default() -> Self {
+        CameraBuilder::default().build().unwrap()
     }
+}
+
+impl Camera {
+    /// Pinhole camera
+    ///
+    /// Panics if the parameters are degenerate; see `CameraBuilder::build`.
     pub fn new(look_from: Point3,
                look_at: Point3,
                vup: Vec3,
                vfov_deg: f64, 
                aspect_ratio: f64) -> Self {
-        let vfov = vfov_deg.to_radians();
-        let h = (vfov/2.).tan();
-        let viewport_height = 2.0 * h;
-        let viewport_width = aspect_ratio * viewport_height;
-
-        let w = (look_from - look_at).unit();
-        let u = vup.cross(w).unit();
-        let v = w.cross(u);
-
-        let origin = look_from;
-        let horizontal = viewport_width * u;
-        let vertical = viewport_height * v;
-        let lower_left_corner =
-            origin - horizontal / 2. - vertical / 2. - w;
-
-        Self {
-            origin,
-            lower_left_corner,
-            horizontal,
-            vertical,
-            u,
-            v,
-            w,
-            lens_radius: 0.,
-        }
+        Self::builder()
+            .position(look_from)
+            .target(look_at)
+            .up(vup)
+            .vfov(vfov_deg)
+            .aspect_ratio(aspect_ratio)
+            .focus_distance(1.)
+            .build()
+            .unwrap_or_else(|e| panic!("invalid camera: {}", e))
     }
+    /// Thin lens camera with an `aperture` diameter lens focused at `focus_dist`
+    ///
+    /// Panics if the parameters are degenerate; see `CameraBuilder::build`.
     pub fn new_with_depth_of_field(look_from: Point3,
                look_at: Point3,
                vup: Vec3,
@@ -75,39 +110,87 @@ impl Camera {
             aperture: f64,
             focus_dist: f64) -> Self 
     {
-        let vfov = vfov_deg.to_radians();
-        let h = (vfov/2.).tan();
-        let viewport_height = 2.0 * h;
-        let viewport_width = aspect_ratio * viewport_height;
+        Self::builder()
+            .position(look_from)
+            .target(look_at)
+            .up(vup)
+            .vfov(vfov_deg)
+            .aspect_ratio(aspect_ratio)
+            .aperture(aperture)
+            .focus_distance(focus_dist)
+            .build()
+            .unwrap_or_else(|e| panic!("invalid camera: {}", e))
+    }
+    pub fn builder() -> CameraBuilder {
+        CameraBuilder::default()
+    }
 
-        let w = (look_from - look_at).unit();
-        let u = vup.cross(w).unit();
-        let v = w.cross(u);
+    pub fn position(&self) -> Point3 {
+        self.look_from
+    }
+    pub fn target(&self) -> Point3 {
+        self.look_at
+    }
+    pub fn up(&self) -> Vec3 {
+        self.vup
+    }
+    /// Vertical field of view in degrees
+    pub fn vfov(&self) -> f64 {
+        self.vfov_deg
+    }
+    pub fn aspect_ratio(&self) -> f64 {
+        self.aspect_ratio
+    }
+    /// Lens diameter; 0 for a pinhole camera
+    pub fn aperture(&self) -> f64 {
+        self.aperture
+    }
+    pub fn focus_distance(&self) -> f64 {
+        self.focus_distance
+    }
+    /// Times at which the shutter opens and closes
+    pub fn shutter(&self) -> (f64, f64) {
+        (self.shutter_open, self.shutter_close)
+    }
+    /// Camera frame: `u` points right, `v` up and the camera looks along -`w`
+    pub fn basis(&self) -> (Vec3, Vec3, Vec3) {
+        (self.u, self.v, self.w)
+    }
+    pub fn aperture_shape(&self) -> &Aperture {
+        &self.aperture_shape
+    }
 
-        let origin = look_from;
-        let horizontal = focus_dist * viewport_width * u;
-        let vertical = focus_dist * viewport_height * v;
-        let lower_left_corner =
-            origin - horizontal / 2. - vertical / 2. - focus_dist*w;
+    /// Shape of the lens opening, scaled to the lens radius
+    pub fn with_aperture(mut self, aperture: Aperture) -> Self {
+        self.aperture_shape = aperture;
+        self
+    }
+    /// Clips the lens opening towards the image corners, as a lens barrel
+    /// does; `strength` is the barrel offset at the image edge in lens radii
+    pub fn with_cat_eye(mut self, strength: f64) -> Self {
+        self.cat_eye = strength;
+        self
+    }
 
-        Self {
-            origin,
-            lower_left_corner,
-            horizontal,
-            vertical,
-            u,
-            v,
-            w,
-            lens_radius: aperture / 2.
-        }
+    fn sample_lens(&self, sampler: &mut dyn Sampler) -> (f64, f64) {
+        sampler.set_dimension(LENS_DIMENSION);
+        self.aperture_shape.sample(sampler)
     }
+    /// Cosine between a direction and the view direction, and the area of
+    /// the image plane at unit distance from the lens
+    fn view_cosine_and_area(&self, direction: Vec3) -> (f64, f64) {
+        let cos_theta = -direction.unit().dot(self.w);
+        let area = self.horizontal.length() * self.vertical.length() / (self.focus_distance * self.focus_distance);
+        (cos_theta, area)
+    }
+
     // The arguments are called u and v in initial sections of the book
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
+    pub fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Ray {
         // Apply depth-of-field if needed
         if self.lens_radius > 0.
         {
-            let rd = self.lens_radius * random_in_unit_disk();
-            let offset = self.u * rd.x() + self.v * rd.y();
+            let (x, y) = self.sample_lens(sampler);
+            let offset = self.lens_radius * (self.u * x + self.v * y);
             Ray::new(
                 self.origin + offset,
                 self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
@@ -120,3 +203,702 @@ impl Camera {
         }
     }
 }
+
+/// Reasons a `CameraBuilder` cannot build a camera
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraError {
+    /// The position and the target are the same point, so there is no view direction
+    TargetAtPosition,
+    /// The up vector is zero or parallel to the view direction
+    UpParallelToView,
+    /// The vertical field of view is not strictly between 0 and 180 degrees
+    InvalidFieldOfView(f64),
+    /// The aspect ratio is not positive and finite
+    InvalidAspectRatio(f64),
+    /// The lens diameter is negative or not finite
+    InvalidAperture(f64),
+    /// The focus distance is not positive and finite
+    InvalidFocusDistance(f64),
+    /// The shutter closes before it opens
+    InvalidShutter(f64, f64),
+    /// The distance between the stereo eyes is negative or not finite
+    InvalidInterpupillaryDistance(f64),
+    /// The stereo eyes do not converge at a positive, finite distance
+    InvalidConvergenceDistance(f64),
+}
+
+impl fmt::Display for CameraError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CameraError::TargetAtPosition => write!(f, "camera target is at the camera position"),
+            CameraError::UpParallelToView => write!(f, "up vector is parallel to the view direction"),
+            CameraError::InvalidFieldOfView(v) => write!(f, "field of view {} is not between 0 and 180 degrees", v),
+            CameraError::InvalidAspectRatio(v) => write!(f, "aspect ratio {} is not positive", v),
+            CameraError::InvalidAperture(v) => write!(f, "aperture {} is negative", v),
+            CameraError::InvalidFocusDistance(v) => write!(f, "focus distance {} is not positive", v),
+            CameraError::InvalidShutter(open, close) => write!(f, "shutter closes at {} before it opens at {}", close, open),
+            CameraError::InvalidInterpupillaryDistance(v) => write!(f, "interpupillary distance {} is negative", v),
+            CameraError::InvalidConvergenceDistance(v) => write!(f, "convergence distance {} is not positive", v),
+        }
+    }
+}
+
+impl std::error::Error for CameraError {}
+
+/// Builds a thin lens `Camera`, checking its parameters
+///
+/// The focus distance defaults to the distance to the target.
+#[derive(Debug, Clone)]
+pub struct CameraBuilder {
+    look_from: Point3,
+    look_at: Point3,
+    vup: Vec3,
+    vfov_deg: f64,
+    aspect_ratio: f64,
+    aperture: f64,
+    focus_distance: Option<f64>,
+    shutter_open: f64,
+    shutter_close: f64,
+}
+
+impl Default for CameraBuilder {
+    fn default() -> Self {
+        Self {
+            look_from: Point3::new(0., 0., 0.),
+            look_at: Point3::new(0., 0., -1.),
+            vup: Vec3::new(0., 1., 0.),
+            vfov_deg: 90.,
+            aspect_ratio: 16. / 9.,
+            aperture: 0.,
+            focus_distance: None,
+            shutter_open: 0.,
+            shutter_close: 0.,
+        }
+    }
+}
+
+impl CameraBuilder {
+    pub fn position(mut self, look_from: Point3) -> Self {
+        self.look_from = look_from;
+        self
+    }
+    pub fn target(mut self, look_at: Point3) -> Self {
+        self.look_at = look_at;
+        self
+    }
+    pub fn up(mut self, vup: Vec3) -> Self {
+        self.vup = vup;
+        self
+    }
+    /// Vertical field of view in degrees
+    pub fn vfov(mut self, vfov_deg: f64) -> Self {
+        self.vfov_deg = vfov_deg;
+        self
+    }
+    pub fn aspect_ratio(mut self, aspect_ratio: f64) -> Self {
+        self.aspect_ratio = aspect_ratio;
+        self
+    }
+    /// Lens diameter; 0 for a pinhole camera
+    pub fn aperture(mut self, aperture: f64) -> Self {
+        self.aperture = aperture;
+        self
+    }
+    pub fn focus_distance(mut self, focus_distance: f64) -> Self {
+        self.focus_distance = Some(focus_distance);
+        self
+    }
+    pub fn shutter(mut self, open: f64, close: f64) -> Self {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self
+    }
+
+    pub fn build(&self) -> Result<Camera, CameraError> {
+        let view = self.look_from - self.look_at;
+        if view.length_squared() == 0. {
+            return Err(CameraError::TargetAtPosition);
+        }
+        let w = view.unit();
+        let side = self.vup.cross(w);
+        if side.length_squared() <= 1e-12 * self.vup.length_squared() || self.vup.length_squared() == 0. {
+            return Err(CameraError::UpParallelToView);
+        }
+        if !(self.vfov_deg > 0. && self.vfov_deg < 180.) {
+            return Err(CameraError::InvalidFieldOfView(self.vfov_deg));
+        }
+        if !(self.aspect_ratio > 0. && self.aspect_ratio.is_finite()) {
+            return Err(CameraError::InvalidAspectRatio(self.aspect_ratio));
+        }
+        if !(self.aperture >= 0. && self.aperture.is_finite()) {
+            return Err(CameraError::InvalidAperture(self.aperture));
+        }
+        let focus_distance = self.focus_distance.unwrap_or_else(|| view.length());
+        if !(focus_distance > 0. && focus_distance.is_finite()) {
+            return Err(CameraError::InvalidFocusDistance(focus_distance));
+        }
+        if self.shutter_close < self.shutter_open {
+            return Err(CameraError::InvalidShutter(self.shutter_open, self.shutter_close));
+        }
+
+        let h = (self.vfov_deg.to_radians() / 2.).tan();
+        let viewport_height = 2.0 * h;
+        let viewport_width = self.aspect_ratio * viewport_height;
+
+        let u = side.unit();
+        let v = w.cross(u);
+
+        let origin = self.look_from;
+        let horizontal = focus_distance * viewport_width * u;
+        let vertical = focus_distance * viewport_height * v;
+        let lower_left_corner =
+            origin - horizontal / 2. - vertical / 2. - focus_distance * w;
+
+        Ok(Camera {
+            look_from: self.look_from,
+            look_at: self.look_at,
+            vup: self.vup,
+            vfov_deg: self.vfov_deg,
+            aspect_ratio: self.aspect_ratio,
+            aperture: self.aperture,
+            focus_distance,
+            shutter_open: self.shutter_open,
+            shutter_close: self.shutter_close,
+            origin,
+            lower_left_corner,
+            horizontal,
+            vertical,
+            u,
+            v,
+            w,
+            lens_radius: self.aperture / 2.,
+            aperture_shape: Aperture::Circular,
+            cat_eye: 0.,
+        })
+    }
+}
+
+impl CameraModel for Camera {
+    fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Ray {
+        Camera::get_ray(self, s, t, sampler)
+    }
+
+    fn try_get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        if self.lens_radius > 0. && self.cat_eye != 0. {
+            // The barrel opening is a unit circle shifted away from the lens
+            // center in proportion to the image position
+            let (cx, cy) = (self.cat_eye * (2. * s - 1.), self.cat_eye * (2. * t - 1.));
+            let (x, y) = self.sample_lens(sampler);
+            if (x - cx).powi(2) + (y - cy).powi(2) > 1. {
+                return None;
+            }
+        }
+        Some(Camera::get_ray(self, s, t, sampler))
+    }
+
+    fn sample_importance(&self, point: Point3, sampler: &mut dyn Sampler) -> Option<ImportanceSample> {
+        // Lens points are chosen as for camera rays, so the lens density
+        // cancels out of the weight
+        let (x, y) = if self.lens_radius > 0. { self.aperture_shape.sample(sampler) } else { (0., 0.) };
+        let lens_point = self.origin + self.lens_radius * (self.u * x + self.v * y);
+        let to_point = point - lens_point;
+        let (cos_theta, area) = self.view_cosine_and_area(to_point);
+        if cos_theta <= 0. {
+            return None;
+        }
+        // Where the ray through the lens point crosses the plane in focus
+        let on_focus_plane = lens_point + self.focus_distance / (to_point.length() * cos_theta) * to_point;
+        let d = on_focus_plane - self.lower_left_corner;
+        let s = d.dot(self.horizontal) / self.horizontal.length_squared();
+        let t = d.dot(self.vertical) / self.vertical.length_squared();
+        if self.lens_radius > 0. && self.cat_eye != 0. {
+            let (cx, cy) = (self.cat_eye * (2. * s - 1.), self.cat_eye * (2. * t - 1.));
+            if (x - cx).powi(2) + (y - cy).powi(2) > 1. {
+                return None;
+            }
+        }
+        // Importance 1 / (A cos^4) over the solid angle density cos / distance^2
+        let weight = 1. / (area * cos_theta.powi(3) * to_point.length_squared());
+        Some(ImportanceSample { s, t, lens_point, weight })
+    }
+
+    fn direction_pdf(&self, direction: Vec3) -> f64 {
+        let (cos_theta, area) = self.view_cosine_and_area(direction);
+        if cos_theta <= 0. { 0. } else { 1. / (area * cos_theta.powi(3)) }
+    }
+}
+
+/// Right-handed camera frame; the camera looks along -w
+#[derive(Debug, Clone, Copy)]
+struct CameraFrame {
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl CameraFrame {
+    fn new(look_from: Point3, look_at: Point3, vup: Vec3) -> Self {
+        let w = (look_from - look_at).unit();
+        let u = vup.cross(w).unit();
+        let v = w.cross(u);
+        Self { origin: look_from, u, v, w }
+    }
+    /// Direction given by its components along u, v and the viewing direction
+    fn direction(&self, x: f64, y: f64, forward: f64) -> Vec3 {
+        x * self.u + y * self.v - forward * self.w
+    }
+}
+
+/// Parallel projection; all rays share the viewing direction
+pub struct OrthographicCamera {
+    frame: CameraFrame,
+    viewport_width: f64,
+    viewport_height: f64,
+}
+
+impl OrthographicCamera {
+    /// `viewport_height` is the height of the visible region in world units
+    pub fn new(look_from: Point3, look_at: Point3, vup: Vec3, viewport_height: f64, aspect_ratio: f64) -> Self {
+        Self {
+            frame: CameraFrame::new(look_from, look_at, vup),
+            viewport_width: aspect_ratio * viewport_height,
+            viewport_height,
+        }
+    }
+}
+
+impl CameraModel for OrthographicCamera {
+    fn get_ray(&self, s: f64, t: f64, _sampler: &mut dyn Sampler) -> Ray {
+        let f = &self.frame;
+        let offset = (s - 0.5) * self.viewport_width * f.u + (t - 0.5) * self.viewport_height * f.v;
+        Ray::new(f.origin + offset, -f.w)
+    }
+}
+
+/// Equidistant fisheye: the angle from the view axis grows linearly with the
+/// distance from the image center
+///
+/// The image circle covering `fov_deg` spans the image height; points beyond
+/// it continue the same mapping up to the backwards direction.
+pub struct FisheyeCamera {
+    frame: CameraFrame,
+    half_fov: f64,
+    aspect_ratio: f64,
+}
+
+impl FisheyeCamera {
+    pub fn new(look_from: Point3, look_at: Point3, vup: Vec3, fov_deg: f64, aspect_ratio: f64) -> Self {
+        Self {
+            frame: CameraFrame::new(look_from, look_at, vup),
+            half_fov: fov_deg.to_radians() / 2.,
+            aspect_ratio,
+        }
+    }
+}
+
+impl CameraModel for FisheyeCamera {
+    fn get_ray(&self, s: f64, t: f64, _sampler: &mut dyn Sampler) -> Ray {
+        let x = (2. * s - 1.) * self.aspect_ratio;
+        let y = 2. * t - 1.;
+        let r = (x * x + y * y).sqrt();
+        let theta = (r * self.half_fov).min(PI);
+        let phi = y.atan2(x);
+        let direction = self.frame.direction(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+        Ray::new(self.frame.origin, direction)
+    }
+}
+
+/// Full spherical panorama in latitude/longitude layout, e.g. for VR
+///
+/// Longitude spans 360 degrees across the image width with the view direction
+/// at the center, latitude spans 180 degrees from bottom to top.
+pub struct EquirectangularCamera {
+    frame: CameraFrame,
+}
+
+impl EquirectangularCamera {
+    pub fn new(look_from: Point3, look_at: Point3, vup: Vec3) -> Self {
+        Self { frame: CameraFrame::new(look_from, look_at, vup) }
+    }
+}
+
+impl CameraModel for EquirectangularCamera {
+    fn get_ray(&self, s: f64, t: f64, _sampler: &mut dyn Sampler) -> Ray {
+        let longitude = (s - 0.5) * 2. * PI;
+        let latitude = (t - 0.5) * PI;
+        let direction = self.frame.direction(
+            latitude.cos() * longitude.sin(),
+            latitude.sin(),
+            latitude.cos() * longitude.cos(),
+        );
+        Ray::new(self.frame.origin, direction)
+    }
+    /// Maps the pixel edges to the image edges, so that the first and last
+    /// columns do not overlap across the seam
+    fn film_ray(&self, x: f64, y: f64, width: u32, height: u32, sampler: &mut dyn Sampler) -> Option<Ray> {
+        self.try_get_ray(x / f64::from(width), y / f64::from(height), sampler)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl Eye {
+    /// Direction of the eye's offset from the rig center along the camera's right vector
+    fn side(&self) -> f64 {
+        match self {
+            Eye::Left => -1.,
+            Eye::Right => 1.,
+        }
+    }
+}
+
+/// Pair of cameras separated by the interpupillary distance
+///
+/// The eyes look in parallel and their image planes are shifted towards each
+/// other (an off-axis projection) so that objects at the convergence
+/// distance appear at the same place in both images, without the vertical
+/// parallax of toed-in cameras.
+#[derive(Debug, Clone)]
+pub struct StereoRig {
+    /// Camera at the center of the rig
+    pub camera: CameraBuilder,
+    pub interpupillary_distance: f64,
+    pub convergence_distance: f64,
+}
+
+impl StereoRig {
+    pub fn new(camera: CameraBuilder, interpupillary_distance: f64, convergence_distance: f64) -> Self {
+        Self { camera, interpupillary_distance, convergence_distance }
+    }
+
+    pub fn eye(&self, eye: Eye) -> Result<Camera, CameraError> {
+        if !(self.interpupillary_distance >= 0. && self.interpupillary_distance.is_finite()) {
+            return Err(CameraError::InvalidInterpupillaryDistance(self.interpupillary_distance));
+        }
+        if !(self.convergence_distance > 0. && self.convergence_distance.is_finite()) {
+            return Err(CameraError::InvalidConvergenceDistance(self.convergence_distance));
+        }
+        let center = self.camera.build()?;
+        let offset = eye.side() * self.interpupillary_distance / 2. * center.u;
+        let mut camera = self.camera.clone()
+            .position(center.look_from + offset)
+            .target(center.look_at + offset)
+            .focus_distance(center.focus_distance)
+            .build()?;
+        // Shift the image plane back towards the rig center so the two frusta
+        // meet at the convergence distance
+        let shift = center.focus_distance / self.convergence_distance;
+        camera.lower_left_corner -= shift * offset;
+        Ok(camera)
+    }
+}
+
+/// Arrangement of the two eye images in one frame
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum StereoLayout {
+    /// Left eye in the left half, right eye in the right half
+    #[default]
+    SideBySide,
+    /// Left eye in the top half, right eye in the bottom half
+    OverUnder,
+}
+
+/// Renders two eye cameras into the halves of one image
+pub struct StereoCamera {
+    left: Box<dyn CameraModel>,
+    right: Box<dyn CameraModel>,
+    layout: StereoLayout,
+}
+
+impl StereoCamera {
+    pub fn new(left: Box<dyn CameraModel>, right: Box<dyn CameraModel>, layout: StereoLayout) -> Self {
+        Self { left, right, layout }
+    }
+    pub fn from_rig(rig: &StereoRig, layout: StereoLayout) -> Result<Self, CameraError> {
+        Ok(Self::new(Box::new(rig.eye(Eye::Left)?), Box::new(rig.eye(Eye::Right)?), layout))
+    }
+    /// Size of the combined image for eye images of the given size
+    pub fn image_size(&self, eye_width: u32, eye_height: u32) -> (u32, u32) {
+        match self.layout {
+            StereoLayout::SideBySide => (2 * eye_width, eye_height),
+            StereoLayout::OverUnder => (eye_width, 2 * eye_height),
+        }
+    }
+
+    /// Eye camera and its image coordinates for a point of the combined image
+    /// given in image coordinates
+    fn eye_coordinates(&self, s: f64, t: f64) -> (&dyn CameraModel, f64, f64) {
+        match self.layout {
+            StereoLayout::SideBySide if s < 0.5 => (self.left.as_ref(), 2. * s, t),
+            StereoLayout::SideBySide => (self.right.as_ref(), 2. * s - 1., t),
+            StereoLayout::OverUnder if t >= 0.5 => (self.left.as_ref(), s, 2. * t - 1.),
+            StereoLayout::OverUnder => (self.right.as_ref(), s, 2. * t),
+        }
+    }
+}
+
+impl CameraModel for StereoCamera {
+    fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Ray {
+        let (camera, s, t) = self.eye_coordinates(s, t);
+        camera.get_ray(s, t, sampler)
+    }
+    fn try_get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let (camera, s, t) = self.eye_coordinates(s, t);
+        camera.try_get_ray(s, t, sampler)
+    }
+    /// Splits the image between pixel columns or rows, so that each eye sees
+    /// its half as an image of its own
+    fn film_ray(&self, x: f64, y: f64, width: u32, height: u32, sampler: &mut dyn Sampler) -> Option<Ray> {
+        match self.layout {
+            StereoLayout::SideBySide => {
+                let eye_width = width / 2;
+                if x < f64::from(eye_width) {
+                    self.left.film_ray(x, y, eye_width, height, sampler)
+                } else {
+                    self.right.film_ray(x - f64::from(eye_width), y, eye_width, height, sampler)
+                }
+            }
+            // Rows count from the bottom, where the right eye is
+            StereoLayout::OverUnder => {
+                let eye_height = height / 2;
+                if y >= f64::from(eye_height) {
+                    self.left.film_ray(x, y - f64::from(eye_height), width, eye_height, sampler)
+                } else {
+                    self.right.film_ray(x, y, width, eye_height, sampler)
+                }
+            }
+        }
+    }
+}
+
+/// One eye of an omni-directional stereo (ODS) panorama
+///
+/// Like `EquirectangularCamera`, but each ray starts on a horizontal circle
+/// of diameter `interpupillary_distance`, offset sideways from its direction
+/// as an eye would be when the viewer turns their head towards it. With a
+/// finite convergence distance the rays are turned inwards to meet there.
+pub struct OdsCamera {
+    frame: CameraFrame,
+    eye: Eye,
+    interpupillary_distance: f64,
+    convergence_distance: Option<f64>,
+}
+
+impl OdsCamera {
+    pub fn new(look_from: Point3, look_at: Point3, vup: Vec3, eye: Eye, interpupillary_distance: f64) -> Self {
+        Self {
+            frame: CameraFrame::new(look_from, look_at, vup),
+            eye,
+            interpupillary_distance,
+            convergence_distance: None,
+        }
+    }
+    pub fn with_convergence(mut self, convergence_distance: f64) -> Self {
+        self.convergence_distance = Some(convergence_distance);
+        self
+    }
+}
+
+impl CameraModel for OdsCamera {
+    fn get_ray(&self, s: f64, t: f64, _sampler: &mut dyn Sampler) -> Ray {
+        let longitude = (s - 0.5) * 2. * PI;
+        let latitude = (t - 0.5) * PI;
+        let f = &self.frame;
+        let direction = f.direction(
+            latitude.cos() * longitude.sin(),
+            latitude.sin(),
+            latitude.cos() * longitude.cos(),
+        );
+        // Right of the horizontal viewing direction
+        let right = f.direction(longitude.cos(), 0., -longitude.sin());
+        let origin = f.origin + self.eye.side() * self.interpupillary_distance / 2. * right;
+        match self.convergence_distance {
+            Some(distance) => Ray::new(origin, f.origin + distance * direction - origin),
+            None => Ray::new(origin, direction),
+        }
+    }
+    /// Maps the pixel edges to the image edges, as `EquirectangularCamera` does
+    fn film_ray(&self, x: f64, y: f64, width: u32, height: u32, sampler: &mut dyn Sampler) -> Option<Ray> {
+        self.try_get_ray(x / f64::from(width), y / f64::from(height), sampler)
+    }
+}
+
+/// Camera projection selectable by name
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Projection {
+    #[default]
+    Perspective,
+    Orthographic,
+    Fisheye,
+    Equirectangular,
+}
+
+impl FromStr for Projection {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "perspective" => Ok(Projection::Perspective),
+            "orthographic" => Ok(Projection::Orthographic),
+            "fisheye" => Ok(Projection::Fisheye),
+            "equirectangular" => Ok(Projection::Equirectangular),
+            _ => Err(format!("unknown projection '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Projection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Projection::Perspective => "perspective",
+            Projection::Orthographic => "orthographic",
+            Projection::Fisheye => "fisheye",
+            Projection::Equirectangular => "equirectangular",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for StereoLayout {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "side-by-side" | "sbs" => Ok(StereoLayout::SideBySide),
+            "over-under" | "top-bottom" => Ok(StereoLayout::OverUnder),
+            _ => Err(format!("unknown stereo layout '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for StereoLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            StereoLayout::SideBySide => "side-by-side",
+            StereoLayout::OverUnder => "over-under",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IndependentSampler;
+
+    fn look_down_z() -> (Point3, Point3, Vec3) {
+        (Point3::new(0., 0., 0.), Point3::new(0., 0., -1.), Vec3::new(0., 1., 0.))
+    }
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_image_center_looks_at_target() {
+        let (from, at, up) = look_down_z();
+        let mut sampler = IndependentSampler::new(0);
+        let cameras: Vec<Box<dyn CameraModel>> = vec![
+            Box::new(Camera::new(from, at, up, 90., 2.)),
+            Box::new(OrthographicCamera::new(from, at, up, 2., 2.)),
+            Box::new(FisheyeCamera::new(from, at, up, 180., 2.)),
+            Box::new(EquirectangularCamera::new(from, at, up)),
+        ];
+        for camera in cameras.iter() {
+            let r = camera.get_ray(0.5, 0.5, &mut sampler);
+            assert_close(r.direction.unit(), Vec3::new(0., 0., -1.));
+        }
+    }
+    #[test]
+    fn test_builder_validation() {
+        let (from, at, up) = look_down_z();
+        assert_eq!(Camera::builder().position(from).target(from).build().err(), Some(CameraError::TargetAtPosition));
+        assert_eq!(Camera::builder().up(Vec3::new(0., 0., 2.)).build().err(), Some(CameraError::UpParallelToView));
+        assert_eq!(Camera::builder().vfov(180.).build().err(), Some(CameraError::InvalidFieldOfView(180.)));
+        assert_eq!(Camera::builder().shutter(1., 0.5).build().err(), Some(CameraError::InvalidShutter(1., 0.5)));
+
+        let camera = Camera::builder().position(from).target(at).up(up).aperture(0.2).shutter(0., 0.5).build().unwrap();
+        assert_eq!(camera.focus_distance(), 1.);
+        assert_eq!(camera.aperture(), 0.2);
+        assert_eq!(camera.shutter(), (0., 0.5));
+    }
+    #[test]
+    fn test_default_camera_basis() {
+        let camera = Camera::default();
+        assert_eq!(camera.basis(), (Vec3::new(1., 0., 0.), Vec3::new(0., 1., 0.), Vec3::new(0., 0., 1.)));
+        // Depth of field works on the default camera too
+        let camera = CameraBuilder::default().aperture(0.5).build().unwrap();
+        let mut sampler = IndependentSampler::new(3);
+        let r = camera.get_ray(0.5, 0.5, &mut sampler);
+        assert!(r.origin.length() > 0. && r.origin.z() == 0.);
+        assert_close(r.at(1.), Point3::new(0., 0., -1.));
+    }
+    #[test]
+    fn test_stereo_eyes_converge() {
+        let (from, at, up) = look_down_z();
+        let rig = StereoRig::new(Camera::builder().position(from).target(at).up(up).focus_distance(2.), 0.1, 5.);
+        let mut sampler = IndependentSampler::new(0);
+        let left = rig.eye(Eye::Left).unwrap().get_ray(0.5, 0.5, &mut sampler);
+        let right = rig.eye(Eye::Right).unwrap().get_ray(0.5, 0.5, &mut sampler);
+        assert_close(left.origin, Point3::new(-0.05, 0., 0.));
+        // Both center rays pass through the convergence point
+        for r in &[left, right] {
+            assert_close(r.at(5. / -r.direction.z()), Point3::new(0., 0., -5.));
+        }
+        assert!(StereoRig::new(Camera::builder(), 0.1, 0.).eye(Eye::Left).is_err());
+    }
+    #[test]
+    fn test_ods_eyes_on_circle() {
+        let (from, at, up) = look_down_z();
+        let mut sampler = IndependentSampler::new(0);
+        let left = OdsCamera::new(from, at, up, Eye::Left, 0.1);
+        // Looking forward the left eye is to the left, looking right it is in front
+        assert_close(left.get_ray(0.5, 0.5, &mut sampler).origin, Point3::new(-0.05, 0., 0.));
+        assert_close(left.get_ray(0.75, 0.5, &mut sampler).origin, Point3::new(0., 0., -0.05));
+        let stereo = StereoCamera::new(Box::new(left), Box::new(OdsCamera::new(from, at, up, Eye::Right, 0.1)), StereoLayout::OverUnder);
+        assert_eq!(stereo.image_size(200, 100), (200, 200));
+        assert_close(stereo.get_ray(0.5, 0.25, &mut sampler).origin, Point3::new(0.05, 0., 0.));
+    }
+    #[test]
+    fn test_stereo_splits_between_pixels() {
+        let (from, at, up) = look_down_z();
+        let mut sampler = IndependentSampler::new(0);
+        let eye = |eye| Box::new(OdsCamera::new(from, at, up, eye, 0.1));
+        let stereo = StereoCamera::new(eye(Eye::Left), eye(Eye::Right), StereoLayout::SideBySide);
+        let (width, height) = stereo.image_size(5, 4);
+        // The whole of the last column of the left eye's image is seen by the left eye
+        let last = stereo.film_ray(4.9, 2., width, height, &mut sampler).unwrap();
+        assert_close(last.origin, eye(Eye::Left).film_ray(4.9, 2., 5, 4, &mut sampler).unwrap().origin);
+        let first = stereo.film_ray(5.1, 2., width, height, &mut sampler).unwrap();
+        assert_close(first.origin, eye(Eye::Right).film_ray(0.1, 2., 5, 4, &mut sampler).unwrap().origin);
+    }
+    #[test]
+    fn test_fisheye_edge_angle() {
+        let (from, at, up) = look_down_z();
+        let camera = FisheyeCamera::new(from, at, up, 180., 1.);
+        let r = camera.get_ray(0.5, 1., &mut IndependentSampler::new(0));
+        assert_close(r.direction.unit(), Vec3::new(0., 1., 0.));
+    }
+    #[test]
+    fn test_equirectangular_wraps_around() {
+        let (from, at, up) = look_down_z();
+        let camera = EquirectangularCamera::new(from, at, up);
+        let mut sampler = IndependentSampler::new(0);
+        assert_close(camera.get_ray(0., 0.5, &mut sampler).direction, Vec3::new(0., 0., 1.));
+        assert_close(camera.get_ray(0.75, 0.5, &mut sampler).direction, Vec3::new(1., 0., 0.));
+        assert_close(camera.get_ray(0.3, 1., &mut sampler).direction, Vec3::new(0., 1., 0.));
+        // The last column ends where the first one starts
+        let first = camera.film_ray(0., 50., 400, 200, &mut sampler).unwrap();
+        assert_close(camera.film_ray(400., 50., 400, 200, &mut sampler).unwrap().direction, first.direction);
+    }
+    #[test]
+    fn test_orthographic_rays_are_parallel() {
+        let (from, at, up) = look_down_z();
+        let camera = OrthographicCamera::new(from, at, up, 2., 1.);
+        let r = camera.get_ray(1., 0., &mut IndependentSampler::new(0));
+        assert_close(r.origin, Point3::new(1., -1., 0.));
+        assert_close(r.direction, Vec3::new(0., 0., -1.));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{Film, SamplerKind};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 1;
+
+/// Saved state of a progressive render
+///
+/// Samplers are a pure function of the seed, the pixel and the sample index,
+/// so the sampler kind, seed and number of samples already taken are all the
+/// random state needed to continue the render exactly where it stopped.
+#[derive(Clone)]
+pub struct Checkpoint {
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    /// Samples per pixel accumulated so far; the next pass starts at this sample index
+    pub samples_taken: u32,
+    pub film: Film,
+}
+
+impl Checkpoint {
+    /// Writes the checkpoint, replacing any previous file only once it is complete
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut w = BufWriter::new(File::create(&tmp_path)?);
+            w.write_all(MAGIC)?;
+            w.write_all(&VERSION.to_le_bytes())?;
+            w.write_all(&self.seed.to_le_bytes())?;
+            w.write_all(&[sampler_tag(self.sampler)])?;
+            w.write_all(&self.samples_taken.to_le_bytes())?;
+            self.film.write_to(&mut w)?;
+            w.flush()?;
+        }
+        fs::rename(tmp_path, path)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Checkpoint> {
+        let mut r = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        let mut version = [0u8; 4];
+        r.read_exact(&mut version)?;
+        if &magic != MAGIC || u32::from_le_bytes(version) != VERSION {
+            return Err(invalid_data("not a render checkpoint"));
+        }
+        let mut seed = [0u8; 8];
+        r.read_exact(&mut seed)?;
+        let mut tag = [0u8];
+        r.read_exact(&mut tag)?;
+        let mut samples_taken = [0u8; 4];
+        r.read_exact(&mut samples_taken)?;
+        Ok(Checkpoint {
+            seed: u64::from_le_bytes(seed),
+            sampler: sampler_from_tag(tag[0])?,
+            samples_taken: u32::from_le_bytes(samples_taken),
+            film: Film::read_from(&mut r)?,
+        })
+    }
+}
+
+fn sampler_tag(kind: SamplerKind) -> u8 {
+    match kind {
+        SamplerKind::Independent => 0,
+        SamplerKind::Stratified => 1,
+        SamplerKind::Halton => 2,
+        SamplerKind::Sobol => 3,
+    }
+}
+
+fn sampler_from_tag(tag: u8) -> io::Result<SamplerKind> {
+    match tag {
+        0 => Ok(SamplerKind::Independent),
+        1 => Ok(SamplerKind::Stratified),
+        2 => Ok(SamplerKind::Halton),
+        3 => Ok(SamplerKind::Sobol),
+        _ => Err(invalid_data("unknown sampler")),
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Color, Filter, FilterKind};
+    #[test]
+    fn test_round_trip() {
+        let mut film = Film::new(3, 2, Filter::new(FilterKind::Mitchell { b: 0.3, c: 0.35 }, 2.));
+        film.add_sample(1.2, 0.4, Color::new(0.25, 1.5, 3.));
+        film.add_sample_count(1, 0, 7);
+        let checkpoint = Checkpoint { seed: 42, sampler: SamplerKind::Sobol, samples_taken: 7, film };
+        let path = std::env::temp_dir().join(format!("raytracer-checkpoint-{}.bin", std::process::id()));
+        checkpoint.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.seed, 42);
+        assert_eq!(loaded.sampler, SamplerKind::Sobol);
+        assert_eq!(loaded.samples_taken, 7);
+        assert_eq!(loaded.film.filter(), checkpoint.film.filter());
+        for y in 0..2 {
+            for x in 0..3 {
+                assert_eq!(loaded.film.pixel(x, y), checkpoint.film.pixel(x, y));
+                assert_eq!(loaded.film.sample_count(x, y), checkpoint.film.sample_count(x, y));
+            }
+        }
+    }
+}
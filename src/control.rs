@@ -0,0 +1,207 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// Receives progress notifications from a renderer
+///
+/// Methods may be called from any of the render threads.
+pub trait RenderObserver: Send + Sync {
+    /// Rendering started; `total` is the number of work units (pixels or samples)
+    fn on_start(&self, _total: u64) {}
+    fn on_progress(&self, _done: u64, _total: u64) {}
+    /// Rendering ended, either after all work or because it was stopped early
+    fn on_finish(&self, _stopped_early: bool) {}
+}
+
+/// Draws an `indicatif` progress bar on the terminal
+pub struct TerminalProgress {
+    bar: ProgressBar,
+}
+
+impl Default for TerminalProgress {
+    fn default() -> Self {
+        let bar = ProgressBar::new(0);
+        bar.set_style(ProgressStyle::default_bar()
+                      .template("{spinner:.green} [{bar:40.cyan/blue}] {percent}% ({elapsed_precise}/{eta_precise})"));
+        Self { bar }
+    }
+}
+
+impl RenderObserver for TerminalProgress {
+    fn on_start(&self, total: u64) {
+        self.bar.set_length(total);
+        self.bar.reset();
+    }
+    fn on_progress(&self, done: u64, _total: u64) {
+        self.bar.set_position(done);
+    }
+    fn on_finish(&self, stopped_early: bool) {
+        if stopped_early {
+            self.bar.abandon_with_message("stopped");
+        } else {
+            self.bar.finish_with_message("done");
+        }
+    }
+}
+
+/// Shared flag used to stop a render from another thread
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Limit on the time spent rendering
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeBudget {
+    /// Elapsed time since the render started
+    WallClock(Duration),
+    /// Time spent tracing samples, summed over all threads
+    SampleTime(Duration),
+}
+
+/// Progress reporting, cancellation and time budget of a render
+///
+/// Pixels not reached when a render is stopped are left empty in the film.
+#[derive(Clone, Default)]
+pub struct RenderControl {
+    pub observer: Option<Arc<dyn RenderObserver>>,
+    pub cancel: CancellationToken,
+    pub budget: Option<TimeBudget>,
+}
+
+impl fmt::Debug for RenderControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RenderControl")
+            .field("observer", &self.observer.is_some())
+            .field("cancel", &self.cancel)
+            .field("budget", &self.budget)
+            .finish()
+    }
+}
+
+impl RenderControl {
+    pub fn with_observer(mut self, observer: Arc<dyn RenderObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+    pub fn with_budget(mut self, budget: TimeBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+}
+
+/// Tracks the progress of a single render call against its `RenderControl`
+pub struct RenderProgress<'a> {
+    control: &'a RenderControl,
+    total: u64,
+    done: AtomicU64,
+    sample_nanos: AtomicU64,
+    start: Instant,
+}
+
+impl<'a> RenderProgress<'a> {
+    pub fn start(control: &'a RenderControl, total: u64) -> Self {
+        if let Some(observer) = &control.observer {
+            observer.on_start(total);
+        }
+        Self {
+            control,
+            total,
+            done: AtomicU64::new(0),
+            sample_nanos: AtomicU64::new(0),
+            start: Instant::now(),
+        }
+    }
+
+    /// Records `units` more work units as completed
+    pub fn advance(&self, units: u64) {
+        let done = self.done.fetch_add(units, Ordering::SeqCst) + units;
+        if let Some(observer) = &self.control.observer {
+            observer.on_progress(done, self.total);
+        }
+    }
+
+    pub fn add_sample_time(&self, elapsed: Duration) {
+        self.sample_nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::SeqCst);
+    }
+
+    /// Whether the render was cancelled or ran out of time
+    pub fn should_stop(&self) -> bool {
+        if self.control.cancel.is_cancelled() {
+            return true;
+        }
+        match self.control.budget {
+            Some(TimeBudget::WallClock(limit)) => self.start.elapsed() >= limit,
+            Some(TimeBudget::SampleTime(limit)) => {
+                Duration::from_nanos(self.sample_nanos.load(Ordering::SeqCst)) >= limit
+            }
+            None => false,
+        }
+    }
+
+    pub fn finish(&self) {
+        if let Some(observer) = &self.control.observer {
+            observer.on_finish(self.done.load(Ordering::SeqCst) < self.total);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<(u64, u64)>>, AtomicBool);
+    impl RenderObserver for Recorder {
+        fn on_progress(&self, done: u64, total: u64) {
+            self.0.lock().unwrap().push((done, total));
+        }
+        fn on_finish(&self, stopped_early: bool) {
+            self.1.store(stopped_early, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_progress_and_cancellation() {
+        let recorder = Arc::new(Recorder::default());
+        let cancel = CancellationToken::new();
+        let control = RenderControl::default()
+            .with_observer(recorder.clone())
+            .with_cancellation(cancel.clone());
+        let progress = RenderProgress::start(&control, 4);
+        progress.advance(1);
+        progress.advance(2);
+        assert!(!progress.should_stop());
+        cancel.cancel();
+        assert!(progress.should_stop());
+        progress.finish();
+        assert_eq!(*recorder.0.lock().unwrap(), vec![(1, 4), (3, 4)]);
+        assert!(recorder.1.load(Ordering::SeqCst));
+    }
+    #[test]
+    fn test_sample_time_budget() {
+        let control = RenderControl::default().with_budget(TimeBudget::SampleTime(Duration::from_millis(10)));
+        let progress = RenderProgress::start(&control, 1);
+        progress.add_sample_time(Duration::from_millis(6));
+        assert!(!progress.should_stop());
+        progress.add_sample_time(Duration::from_millis(6));
+        assert!(progress.should_stop());
+    }
+}
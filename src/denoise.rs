@@ -0,0 +1,205 @@
+use crate::{luminance, AovBuffers, Color, Film, Vec3};
+use rayon::prelude::*;
+
+/// Edge-avoiding à-trous wavelet denoiser (Dammertz et al. 2010)
+///
+/// The image is demodulated by the albedo buffer so that texture detail is not
+/// blurred, then smoothed with a 5x5 B3-spline kernel whose taps are spread
+/// 1, 2, 4, ... pixels apart on successive iterations. Each tap is weighted by
+/// how similar its color, normal, albedo and depth are to the center pixel,
+/// which keeps the filter from crossing geometric and material edges.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Denoiser {
+    /// Wavelet iterations; those whose taps would all fall outside the image
+    /// are skipped, as they leave it unchanged
+    pub iterations: u32,
+    /// Luminance difference tolerated, relative to the brighter pixel, on the
+    /// first iteration; it shrinks by a factor sqrt(2) on each one after
+    pub color_sigma: f64,
+    pub normal_sigma: f64,
+    pub albedo_sigma: f64,
+    /// Depth difference tolerated, relative to the depth of the center pixel
+    pub depth_sigma: f64,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            color_sigma: 0.5,
+            normal_sigma: 0.3,
+            albedo_sigma: 0.1,
+            depth_sigma: 0.05,
+        }
+    }
+}
+
+const KERNEL: [f64; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+/// Albedo channels below this are not divided out
+const MIN_ALBEDO: f64 = 0.01;
+
+impl Denoiser {
+    pub fn new(iterations: u32) -> Self {
+        Self { iterations, ..Self::default() }
+    }
+
+    /// Returns a copy of the film with its radiance denoised, guided by the
+    /// first-hit buffers of the same render
+    pub fn denoise(&self, film: &Film, aovs: &AovBuffers) -> Film {
+        let (width, height) = (film.width(), film.height());
+        assert!(aovs.width() == width && aovs.height() == height,
+                "AOV buffers do not match the film size");
+        let pixels = || (0..height).flat_map(move |y| (0..width).map(move |x| (x, y)));
+        let modulation: Vec<Color> = pixels().map(|(x, y)| demodulation_factor(aovs.albedo(x, y))).collect();
+        let guides: Vec<Guide> = pixels()
+            .map(|(x, y)| Guide { normal: aovs.normal(x, y), albedo: aovs.albedo(x, y), depth: aovs.depth(x, y) })
+            .collect();
+        let mut irradiance: Vec<Color> = pixels()
+            .zip(modulation.iter())
+            .map(|((x, y), m)| divide(film.pixel(x, y), *m))
+            .collect();
+
+        // Iteration n spreads its taps 2^n pixels apart
+        let useful_iterations = 32 - (width.max(height).max(1) - 1).leading_zeros();
+        for iteration in 0..self.iterations.min(useful_iterations) {
+            let step = 1i64 << iteration;
+            let color_sigma = self.color_sigma * 0.5f64.powf(iteration as f64 / 2.);
+            let src = &irradiance;
+            irradiance = (0..(width * height) as usize)
+                .into_par_iter()
+                .map(|idx| {
+                    let (x, y) = ((idx as u32 % width) as i64, (idx as u32 / width) as i64);
+                    let (c, g) = (src[idx], &guides[idx]);
+                    let mut sum = Color::default();
+                    let mut weight_sum = 0.;
+                    for (ky, wy) in KERNEL.iter().enumerate() {
+                        for (kx, wx) in KERNEL.iter().enumerate() {
+                            let qx = x + (kx as i64 - 2) * step;
+                            let qy = y + (ky as i64 - 2) * step;
+                            if qx < 0 || qy < 0 || qx >= width as i64 || qy >= height as i64 {
+                                continue;
+                            }
+                            let q = (qy * width as i64 + qx) as usize;
+                            let w = wx * wy * self.edge_weight(c, src[q], color_sigma, g, &guides[q]);
+                            sum += w * src[q];
+                            weight_sum += w;
+                        }
+                    }
+                    // The center tap always has a positive weight
+                    sum / weight_sum
+                })
+                .collect();
+        }
+
+        let mut output = film.clone();
+        for ((x, y), (c, m)) in pixels().zip(irradiance.iter().zip(modulation.iter())) {
+            output.set_pixel(x, y, *c * *m);
+        }
+        output
+    }
+
+    fn edge_weight(&self, c: Color, cq: Color, color_sigma: f64, g: &Guide, gq: &Guide) -> f64 {
+        let depth_weight = match (g.depth.is_finite(), gq.depth.is_finite()) {
+            (true, true) => {
+                let dz = (g.depth - gq.depth) / (g.depth * self.depth_sigma);
+                (-dz * dz).exp()
+            }
+            (false, false) => 1.,
+            // Never mix surfaces with the background
+            _ => return 0.,
+        };
+        let gaussian = |d: Vec3, sigma: f64| (-d.length_squared() / (sigma * sigma)).exp();
+        let (l, lq) = (luminance(c), luminance(cq));
+        let dl = (l - lq) / l.max(lq).max(1e-3);
+        depth_weight
+            * (-dl * dl / (color_sigma * color_sigma)).exp()
+            * gaussian(g.normal - gq.normal, self.normal_sigma)
+            * gaussian(g.albedo - gq.albedo, self.albedo_sigma)
+    }
+}
+
+/// First-hit features of a pixel used to stop the filter at edges
+struct Guide {
+    normal: Vec3,
+    albedo: Color,
+    depth: f64,
+}
+
+fn demodulation_factor(albedo: Color) -> Color {
+    let channel = |a: f64| if a > MIN_ALBEDO { a } else { 1. };
+    Color::new(channel(albedo.x()), channel(albedo.y()), channel(albedo.z()))
+}
+
+fn divide(c: Color, m: Color) -> Color {
+    Color::new(c.x() / m.x(), c.y() / m.y(), c.z() / m.z())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AovSample, Filter, Point3, mix};
+
+    fn noisy_film(width: u32, height: u32, color_at: impl Fn(u32) -> Color) -> Film {
+        let mut film = Film::new(width, height, Filter::default());
+        for y in 0..height {
+            for x in 0..width {
+                let noise = (mix(u64::from(y * width + x)) % 1000) as f64 / 1000. - 0.5;
+                film.add_sample(x as f64 + 0.5, y as f64 + 0.5, color_at(x) * (1. + noise));
+            }
+        }
+        film
+    }
+
+    fn aovs(width: u32, height: u32, albedo_at: impl Fn(u32) -> Color) -> AovBuffers {
+        let mut aovs = AovBuffers::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                aovs.add_sample(x, y, &AovSample {
+                    depth: 1.,
+                    normal: Vec3::new(0., 0., 1.),
+                    albedo: albedo_at(x),
+                    position: Point3::default(),
+                    material_id: 1,
+                    object_id: 1,
+                });
+            }
+        }
+        aovs
+    }
+
+    #[test]
+    fn test_reduces_noise() {
+        let gray = Color::new(0.5, 0.5, 0.5);
+        let film = noisy_film(32, 32, |_| gray);
+        let denoised = Denoiser::default().denoise(&film, &aovs(32, 32, |_| gray));
+        let error = |f: &Film| -> f64 {
+            (0..32).flat_map(|y| (0..32).map(move |x| (x, y)))
+                .map(|(x, y)| (f.pixel(x, y) - gray).length_squared())
+                .sum()
+        };
+        assert!(error(&denoised) < 0.1 * error(&film));
+    }
+
+    #[test]
+    fn test_preserves_albedo_edge() {
+        let albedo_at = |x: u32| if x < 16 { Color::new(0.9, 0.1, 0.1) } else { Color::new(0.1, 0.1, 0.9) };
+        let film = noisy_film(32, 8, albedo_at);
+        let denoised = Denoiser::default().denoise(&film, &aovs(32, 8, albedo_at));
+        for y in 0..8 {
+            assert!(denoised.pixel(15, y).x() > 0.5);
+            assert!(denoised.pixel(16, y).x() < 0.2);
+        }
+    }
+
+    #[test]
+    fn test_many_iterations() {
+        let gray = Color::new(0.5, 0.5, 0.5);
+        let film = noisy_film(5, 3, |_| gray);
+        let denoised = Denoiser::new(3).denoise(&film, &aovs(5, 3, |_| gray));
+        let many = Denoiser::new(u32::MAX).denoise(&film, &aovs(5, 3, |_| gray));
+        for x in 0..5 {
+            assert_eq!(many.pixel(x, 1), denoised.pixel(x, 1));
+        }
+    }
+}
@@ -0,0 +1,448 @@
+use crate::{clamp, AovBuffers, AovSample, Color, ToneMap};
+use image::RgbImage;
+use std::f64::consts::PI;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+/// Shape of a pixel reconstruction filter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian { alpha: f64 },
+    Mitchell { b: f64, c: f64 },
+    /// Sinc windowed by a wider sinc with `tau` lobes
+    Lanczos { tau: f64 },
+}
+
+/// Separable reconstruction filter with a radius measured in pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f64,
+}
+
+impl Default for Filter {
+    // Equivalent to averaging the samples inside each pixel
+    fn default() -> Self {
+        Self::new(FilterKind::Box, 0.5)
+    }
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, radius: f64) -> Self {
+        Self { kind, radius }
+    }
+
+    /// Weight of a sample offset by (dx, dy) from a pixel center
+    pub fn evaluate(&self, dx: f64, dy: f64) -> f64 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let r = self.radius;
+        let x = x.abs();
+        if x > r {
+            return 0.;
+        }
+        match self.kind {
+            FilterKind::Box => 1.,
+            FilterKind::Tent => r - x,
+            FilterKind::Gaussian { alpha } => {
+                ((-alpha * x * x).exp() - (-alpha * r * r).exp()).max(0.)
+            }
+            FilterKind::Mitchell { b, c } => {
+                // Remap to the [0, 2] domain of the original kernel
+                let x = 2. * x / r;
+                let poly = if x > 1. {
+                    (-b - 6. * c) * x * x * x
+                        + (6. * b + 30. * c) * x * x
+                        + (-12. * b - 48. * c) * x
+                        + (8. * b + 24. * c)
+                } else {
+                    (12. - 9. * b - 6. * c) * x * x * x
+                        + (-18. + 12. * b + 6. * c) * x * x
+                        + (6. - 2. * b)
+                };
+                poly / 6.
+            }
+            FilterKind::Lanczos { tau } => sinc(x) * sinc(x / tau),
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+impl FromStr for FilterKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "box" => Ok(FilterKind::Box),
+            "tent" => Ok(FilterKind::Tent),
+            "gaussian" => Ok(FilterKind::Gaussian { alpha: 2. }),
+            "mitchell" => Ok(FilterKind::Mitchell { b: 1. / 3., c: 1. / 3. }),
+            "lanczos" => Ok(FilterKind::Lanczos { tau: 3. }),
+            _ => Err(format!("unknown filter '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for FilterKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            FilterKind::Box => "box",
+            FilterKind::Tent => "tent",
+            FilterKind::Gaussian { .. } => "gaussian",
+            FilterKind::Mitchell { .. } => "mitchell",
+            FilterKind::Lanczos { .. } => "lanczos",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct FilmPixel {
+    sum: Color,
+    weight: f64,
+}
+
+/// Linear radiance buffer that accumulates filtered sample splats
+///
+/// Sample positions are continuous pixel coordinates with the origin at the
+/// top-left corner of the image, so pixel (x, y) is centered on (x + 0.5, y + 0.5).
+/// A film may cover only a region of the image (e.g. a tile); all positions are
+/// still given in image coordinates and splats outside the region are dropped.
+/// The first-hit AOVs of the camera samples can be recorded alongside.
+#[derive(Clone)]
+pub struct Film {
+    x0: u32,
+    y0: u32,
+    width: u32,
+    height: u32,
+    filter: Filter,
+    pixels: Vec<FilmPixel>,
+    /// Number of camera samples taken inside each pixel
+    sample_counts: Vec<u32>,
+    /// First hits of the camera samples taken inside each pixel, if recorded
+    aovs: Option<AovBuffers>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Filter) -> Self {
+        Self::new_region(0, 0, width, height, filter)
+    }
+    /// Film covering the pixels [x0, x0 + width) x [y0, y0 + height) of an image
+    pub fn new_region(x0: u32, y0: u32, width: u32, height: u32, filter: Filter) -> Self {
+        Self {
+            x0,
+            y0,
+            width,
+            height,
+            filter,
+            pixels: vec![FilmPixel::default(); (width * height) as usize],
+            sample_counts: vec![0; (width * height) as usize],
+            aovs: None,
+        }
+    }
+    /// Also records the first-hit AOVs of the samples taken, see `add_aov_sample`
+    pub fn with_aovs(mut self) -> Self {
+        self.aovs = Some(AovBuffers::new(self.width, self.height));
+        self
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        ((y - self.y0) * self.width + (x - self.x0)) as usize
+    }
+
+    /// Splats a radiance sample onto every pixel within the filter radius
+    pub fn add_sample(&mut self, x: f64, y: f64, c: Color) {
+        for (idx, w) in self.footprint(x, y) {
+            let p = &mut self.pixels[idx];
+            p.sum += w * c;
+            p.weight += w;
+        }
+    }
+
+    /// Spreads `c` over the pixels within the filter radius, in proportion to
+    /// the filter weights, for light paths reaching the film at random
+    /// positions. Unlike samples, splats add up instead of being averaged, so
+    /// they go into a film of their own, added to the image with `add_splats`.
+    pub fn add_splat(&mut self, x: f64, y: f64, c: Color) {
+        let footprint = self.footprint(x, y);
+        let total: f64 = footprint.iter().map(|&(_, w)| w).sum();
+        if total.abs() < 1e-12 {
+            return;
+        }
+        for (idx, w) in footprint {
+            self.pixels[idx].sum += w / total * c;
+        }
+    }
+
+    /// Adds the splats of a film filled with `add_splat`, scaled by `scale`,
+    /// to the reconstructed radiance
+    pub fn add_splats(&mut self, splats: &Film, scale: f64) {
+        for y in splats.y0..splats.y0 + splats.height {
+            for x in splats.x0..splats.x0 + splats.width {
+                let splat = splats.pixels[splats.index(x, y)].sum;
+                if splat != Color::default() {
+                    self.set_pixel(x, y, self.pixel(x, y) + scale * splat);
+                }
+            }
+        }
+    }
+
+    /// Indices and filter weights of the pixels of this film whose centers lie
+    /// within the filter radius of (x, y)
+    fn footprint(&self, x: f64, y: f64) -> Vec<(usize, f64)> {
+        let r = self.filter.radius;
+        // Pixels whose centers lie in [x - r, x + r)
+        let (fx0, fy0) = (self.x0 as i64, self.y0 as i64);
+        let x0 = ((x - 0.5 - r).floor() as i64 + 1).max(fx0);
+        let x1 = ((x - 0.5 + r).floor() as i64).min(fx0 + self.width as i64 - 1);
+        let y0 = ((y - 0.5 - r).floor() as i64 + 1).max(fy0);
+        let y1 = ((y - 0.5 + r).floor() as i64).min(fy0 + self.height as i64 - 1);
+        let mut footprint = Vec::new();
+        for py in y0..=y1 {
+            for px in x0..=x1 {
+                let w = self.filter.evaluate(x - (px as f64 + 0.5), y - (py as f64 + 0.5));
+                if w != 0. {
+                    footprint.push((self.index(px as u32, py as u32), w));
+                }
+            }
+        }
+        footprint
+    }
+
+    /// Reconstructed linear radiance of a pixel
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        let p = &self.pixels[self.index(x, y)];
+        if p.weight.abs() < 1e-12 {
+            Color::default()
+        } else {
+            p.sum / p.weight
+        }
+    }
+
+    /// Replaces the accumulated radiance of a pixel, e.g. after filtering the image
+    pub fn set_pixel(&mut self, x: u32, y: u32, c: Color) {
+        let idx = self.index(x, y);
+        self.pixels[idx] = FilmPixel { sum: c, weight: 1. };
+    }
+
+    pub fn add_sample_count(&mut self, x: u32, y: u32, count: u32) {
+        let idx = self.index(x, y);
+        self.sample_counts[idx] += count;
+    }
+
+    pub fn sample_count(&self, x: u32, y: u32) -> u32 {
+        self.sample_counts[self.index(x, y)]
+    }
+
+    /// Adds the first hit of a camera sample taken inside the image pixel
+    /// (x, y); ignored unless the film records AOVs
+    pub fn add_aov_sample(&mut self, x: u32, y: u32, sample: &AovSample) {
+        let (x0, y0) = (self.x0, self.y0);
+        if let Some(aovs) = &mut self.aovs {
+            aovs.add_sample(x - x0, y - y0, sample);
+        }
+    }
+
+    /// AOVs recorded with the radiance, covering the film's region
+    pub fn aovs(&self) -> Option<&AovBuffers> {
+        self.aovs.as_ref()
+    }
+
+    /// Adds the splats, sample counts and AOVs of a film covering a sub-region of this one
+    pub fn merge(&mut self, other: &Film) {
+        for y in other.y0..other.y0 + other.height {
+            for x in other.x0..other.x0 + other.width {
+                let (src, dst) = (other.index(x, y), self.index(x, y));
+                self.pixels[dst].sum += other.pixels[src].sum;
+                self.pixels[dst].weight += other.pixels[src].weight;
+                self.sample_counts[dst] += other.sample_counts[src];
+                if let (Some(aovs), Some(other_aovs)) = (&mut self.aovs, &other.aovs) {
+                    aovs.add_pixel(x - self.x0, y - self.y0, other_aovs, x - other.x0, y - other.y0);
+                }
+            }
+        }
+    }
+
+    /// Heatmap of per-pixel sample counts, from blue (fewest) to red (most)
+    pub fn sample_count_image(&self) -> RgbImage {
+        let max = self.sample_counts.iter().copied().max().unwrap_or(0).max(1);
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            let t = self.sample_count(self.x0 + x, self.y0 + y) as f64 / max as f64;
+            image::Rgb(heatmap_color(t).to_rgb())
+        })
+    }
+
+    pub fn to_rgb_image(&self, tone_map: &ToneMap) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            image::Rgb(tone_map.to_rgb(self.pixel(self.x0 + x, self.y0 + y)))
+        })
+    }
+}
+
+impl Film {
+    /// Writes the raw accumulation buffers (little-endian), e.g. for checkpoints.
+    /// Recorded AOVs are not written.
+    pub fn write_to(&self, w: &mut dyn Write) -> io::Result<()> {
+        for v in &[self.x0, self.y0, self.width, self.height] {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        let (tag, a, b) = match self.filter.kind {
+            FilterKind::Box => (0u8, 0., 0.),
+            FilterKind::Tent => (1, 0., 0.),
+            FilterKind::Gaussian { alpha } => (2, alpha, 0.),
+            FilterKind::Mitchell { b, c } => (3, b, c),
+            FilterKind::Lanczos { tau } => (4, tau, 0.),
+        };
+        w.write_all(&[tag])?;
+        for v in &[a, b, self.filter.radius] {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        for (p, count) in self.pixels.iter().zip(self.sample_counts.iter()) {
+            for v in &[p.sum.x(), p.sum.y(), p.sum.z(), p.weight] {
+                w.write_all(&v.to_le_bytes())?;
+            }
+            w.write_all(&count.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Reads a film written by `write_to`
+    pub fn read_from(r: &mut dyn Read) -> io::Result<Film> {
+        let (x0, y0, width, height) = (read_u32(r)?, read_u32(r)?, read_u32(r)?, read_u32(r)?);
+        let mut tag = [0u8];
+        r.read_exact(&mut tag)?;
+        let (a, b, radius) = (read_f64(r)?, read_f64(r)?, read_f64(r)?);
+        let kind = match tag[0] {
+            0 => FilterKind::Box,
+            1 => FilterKind::Tent,
+            2 => FilterKind::Gaussian { alpha: a },
+            3 => FilterKind::Mitchell { b: a, c: b },
+            4 => FilterKind::Lanczos { tau: a },
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown filter")),
+        };
+        let mut film = Film::new_region(x0, y0, width, height, Filter::new(kind, radius));
+        for i in 0..film.pixels.len() {
+            let sum = Color::new(read_f64(r)?, read_f64(r)?, read_f64(r)?);
+            film.pixels[i] = FilmPixel { sum, weight: read_f64(r)? };
+            film.sample_counts[i] = read_u32(r)?;
+        }
+        Ok(film)
+    }
+}
+
+fn read_u32(r: &mut dyn Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_f64(r: &mut dyn Read) -> io::Result<f64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(f64::from_le_bytes(buf))
+}
+
+/// Maps [0, 1] onto a blue-cyan-green-yellow-red ramp
+pub fn heatmap_color(t: f64) -> Color {
+    const RAMP: [(f64, f64, f64); 5] = [
+        (0., 0., 1.),
+        (0., 1., 1.),
+        (0., 1., 0.),
+        (1., 1., 0.),
+        (1., 0., 0.),
+    ];
+    let x = clamp(t, 0., 1.) * (RAMP.len() - 1) as f64;
+    let i = (x as usize).min(RAMP.len() - 2);
+    let f = x - i as f64;
+    let (a, b) = (RAMP[i], RAMP[i + 1]);
+    Color::new(a.0 + f * (b.0 - a.0), a.1 + f * (b.1 - a.1), a.2 + f * (b.2 - a.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_box_filter_averages_within_pixel() {
+        let mut film = Film::new(2, 1, Filter::default());
+        film.add_sample(0.0, 0.5, Color::new(1., 1., 1.));
+        film.add_sample(0.9, 0.2, Color::new(3., 3., 3.));
+        film.add_sample(1.0, 0.5, Color::new(5., 5., 5.));
+        assert_eq!(film.pixel(0, 0), Color::new(2., 2., 2.));
+        assert_eq!(film.pixel(1, 0), Color::new(5., 5., 5.));
+    }
+    #[test]
+    fn test_wide_filter_splats_to_neighbours() {
+        let kinds = ["tent", "gaussian", "mitchell", "lanczos"];
+        for kind in kinds.iter() {
+            let mut film = Film::new(3, 3, Filter::new(kind.parse().unwrap(), 1.5));
+            film.add_sample(1.3, 1.6, Color::new(1., 1., 1.));
+            for y in 0..3 {
+                for x in 0..3 {
+                    assert!((film.pixel(x, y).x() - 1.).abs() < 1e-9, "{} at ({}, {})", kind, x, y);
+                }
+            }
+        }
+    }
+    #[test]
+    fn test_splats_add_up() {
+        let mut film = Film::new(3, 1, Filter::default());
+        film.add_sample(1.5, 0.5, Color::new(1., 1., 1.));
+        let mut splats = Film::new(3, 1, Filter::new(FilterKind::Tent, 1.));
+        splats.add_splat(1.5, 0.5, Color::new(2., 2., 2.));
+        splats.add_splat(1.2, 0.5, Color::new(2., 2., 2.));
+        film.add_splats(&splats, 0.5);
+        let total: f64 = (0..3).map(|x| film.pixel(x, 0).x()).sum();
+        assert!((total - 3.).abs() < 1e-12);
+        assert!(film.pixel(0, 0).x() > 0. && film.pixel(1, 0).x() > 2.);
+    }
+    #[test]
+    fn test_merged_regions_match_full_film() {
+        let filter = Filter::new(FilterKind::Tent, 1.5);
+        let samples = [(1.2, 0.7, 1.), (2.9, 1.1, 2.), (3.5, 3.9, 3.), (0.1, 3.3, 4.)];
+        let mut full = Film::new(4, 4, filter);
+        let mut merged = Film::new(4, 4, filter);
+        let mut top = Film::new_region(0, 0, 4, 2, filter);
+        let mut bottom = Film::new_region(0, 2, 4, 2, filter);
+        for &(x, y, c) in samples.iter() {
+            full.add_sample(x, y, Color::new(c, c, c));
+            top.add_sample(x, y, Color::new(c, c, c));
+            bottom.add_sample(x, y, Color::new(c, c, c));
+        }
+        merged.merge(&top);
+        merged.merge(&bottom);
+        for y in 0..4 {
+            for x in 0..4 {
+                assert_eq!(full.pixel(x, y), merged.pixel(x, y));
+            }
+        }
+    }
+    #[test]
+    fn test_filters_peak_at_center() {
+        let kinds = ["box", "tent", "gaussian", "mitchell", "lanczos"];
+        for kind in kinds.iter() {
+            let f = Filter::new(kind.parse().unwrap(), 2.);
+            assert!(f.evaluate(0., 0.) >= f.evaluate(0.7, 0.3), "{}", kind);
+            assert_eq!(f.evaluate(2.5, 0.), 0.);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{Point3, Ray, Vec3, Material, Color};
+use crate::{Point3, Ray, Vec3, Material, Color, Sampler, Light, Environment, GradientSky};
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 pub struct HitRecord {
@@ -7,6 +7,11 @@ pub struct HitRecord {
     pub distance: f64,
     pub is_front_face: bool,
     pub material: Option<Arc<dyn Material + Sync + Send>>,
+    /// One plus the index of the top-level scene object that was hit, 0 if unknown
+    pub object_id: u32,
+    /// Surface coordinates in [0, 1], 0 for objects without a parameterization
+    pub u: f64,
+    pub v: f64,
 }
 
 impl HitRecord {
@@ -23,6 +28,9 @@ impl HitRecord {
             normal,
             is_front_face,
             material: None,
+            object_id: 0,
+            u: 0.,
+            v: 0.,
         }
     }
     pub fn new_with_material(outward_normal: Vec3, 
@@ -36,36 +44,130 @@ impl HitRecord {
         rec
     }
 
-    pub fn scatter(&self, r: &Ray) -> Option<(Ray, Color)>
+    pub fn scatter(&self, r: &Ray, sampler: &mut dyn Sampler) -> Option<(Ray, Color)>
     {
-        self.material.as_ref().map(|m|m.scatter(r, self)).flatten()
+        self.material.as_ref().and_then(|m|m.scatter(r, self, sampler))
+    }
+
+    /// Whether rays scattered here must skip lights already counted by `direct_light`
+    pub fn shades_lights_directly(&self) -> bool {
+        self.material.as_ref().is_some_and(|m| m.shades_lights_directly())
+    }
+
+    /// Light from the scene's lights reflected along `r`, tracing a shadow ray to each
+    pub fn direct_light(&self, r: &Ray, world: &HittableList) -> Color {
+        let material = match &self.material {
+            Some(m) => m,
+            None => return Color::default(),
+        };
+        let mut total = Color::default();
+        for light in world.lights() {
+            if let Some(sample) = light.sample(self.point) {
+                let f = material.eval(r, self, sample.direction);
+                if f == Color::default() {
+                    continue;
+                }
+                let shadow_ray = Ray::new(self.point, sample.direction);
+                if !world.occluded(&shadow_ray, 0.001, sample.distance) {
+                    total += f * sample.irradiance;
+                }
+            }
+        }
+        total
     }
 }
 pub trait Hittable {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+
+    /// Whether anything is hit between `t_min` and `t_max`. Unlike `hit` this
+    /// may stop at the first intersection found and builds no `HitRecord`,
+    /// which makes it the query to use for shadow rays.
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.hit(r, t_min, t_max).is_some()
+    }
+
+    /// Appends the materials used by this object, in a stable order
+    fn collect_materials(&self, _materials: &mut Vec<Arc<dyn Material + Sync + Send>>) {}
+}
+
+/// Lets scenes share objects, e.g. between the frames of an animation
+impl<T: Hittable + ?Sized> Hittable for Arc<T> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        (**self).hit(r, t_min, t_max)
+    }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        (**self).occluded(r, t_min, t_max)
+    }
+    fn collect_materials(&self, materials: &mut Vec<Arc<dyn Material + Sync + Send>>) {
+        (**self).collect_materials(materials)
+    }
 }
 
-pub struct HittableList(Vec<Box<dyn Hittable + Sync + Send>>);
+/// Objects of a scene together with the analytic lights shining on them and
+/// the environment surrounding them
+pub struct HittableList {
+    objects: Vec<Box<dyn Hittable + Sync + Send>>,
+    lights: Vec<Arc<dyn Light + Sync + Send>>,
+    environment: Arc<dyn Environment + Sync + Send>,
+}
 impl HittableList {
     pub fn new() -> Self {
-        Self(Vec::new())
+        Self { objects: Vec::new(), lights: Vec::new(), environment: Arc::new(GradientSky) }
     }
     pub fn add(&mut self, object: Box<dyn Hittable + Sync + Send>) {
-        self.0.push(object);
+        self.objects.push(object);
+    }
+    pub fn add_light(&mut self, light: Arc<dyn Light + Sync + Send>) {
+        self.lights.push(light);
+    }
+    pub fn lights(&self) -> &[Arc<dyn Light + Sync + Send>] {
+        &self.lights
+    }
+    pub fn set_environment(&mut self, environment: Arc<dyn Environment + Sync + Send>) {
+        self.environment = environment;
+    }
+    pub fn environment(&self) -> &Arc<dyn Environment + Sync + Send> {
+        &self.environment
+    }
+
+    /// Light arriving along a ray that leaves the scene, including the lights
+    /// with a visible extent unless the last surface already shaded with them
+    pub fn background(&self, r: &Ray, include_lights: bool) -> Color {
+        let mut radiance = self.environment.radiance(r.direction);
+        if include_lights {
+            for light in self.lights.iter() {
+                radiance += light.emitted(r.direction);
+            }
+        }
+        radiance
+    }
+}
+impl Default for HittableList {
+    fn default() -> Self {
+        Self::new()
     }
 }
 impl Hittable for HittableList {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         let mut closest_so_far = t_max;
         let mut output: Option<HitRecord> = None;
-        for item in self.0.iter() {
-            if let Some(rec) = item.hit(r, t_min, closest_so_far) {
+        for (idx, item) in self.objects.iter().enumerate() {
+            if let Some(mut rec) = item.hit(r, t_min, closest_so_far) {
                 closest_so_far = rec.distance;
+                rec.object_id = idx as u32 + 1;
                 output = Some(rec);
             }
         }
         output
     }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.objects.iter().any(|item| item.occluded(r, t_min, t_max))
+    }
+    fn collect_materials(&self, materials: &mut Vec<Arc<dyn Material + Sync + Send>>) {
+        for item in self.objects.iter() {
+            item.collect_materials(materials);
+        }
+    }
 }
 
 // Allow use of HittableList like a vector
@@ -73,12 +175,12 @@ impl Deref for HittableList {
     type Target = Vec<Box<dyn Hittable + Sync + Send>>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.objects
     }
 }
 
 impl DerefMut for HittableList {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.objects
     }
 }
@@ -0,0 +1,478 @@
+use crate::{random_in_unit_disk, CameraModel, Point3, Ray, Sampler, Vec3, LENS_DIMENSION};
+use image::GrayImage;
+use std::f64::consts::PI;
+use std::fmt;
+use std::sync::Arc;
+
+/// Shape of a camera aperture, in coordinates scaled to the unit disk
+#[derive(Clone, Default)]
+pub enum Aperture {
+    #[default]
+    Circular,
+    /// Regular polygon formed by straight diaphragm blades
+    Polygon { blades: u32, rotation: f64 },
+    /// Arbitrary shape given by a grayscale image
+    Mask(Arc<ApertureMask>),
+}
+
+impl fmt::Debug for Aperture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Aperture::Circular => write!(f, "Circular"),
+            Aperture::Polygon { blades, rotation } => write!(f, "Polygon({} blades, {} rad)", blades, rotation),
+            Aperture::Mask(mask) => write!(f, "Mask({}x{})", mask.width, mask.height),
+        }
+    }
+}
+
+impl Aperture {
+    pub fn polygon(blades: u32) -> Self {
+        Aperture::Polygon { blades: blades.max(3), rotation: 0. }
+    }
+
+    /// Samples a point inside the aperture
+    pub fn sample(&self, sampler: &mut dyn Sampler) -> (f64, f64) {
+        match self {
+            Aperture::Circular => {
+                let p = random_in_unit_disk(sampler);
+                (p.x(), p.y())
+            }
+            Aperture::Polygon { blades, rotation } => {
+                let (u1, u2) = sampler.get_2d();
+                // Pick one of the triangles fanning out from the center, reusing
+                // the remainder of u1 to place the point inside it
+                let n = *blades as f64;
+                let k = (u1 * n).floor().min(n - 1.);
+                let a = (u1 * n - k).sqrt();
+                let (x0, y0) = polygon_vertex(k, n, *rotation);
+                let (x1, y1) = polygon_vertex(k + 1., n, *rotation);
+                (a * ((1. - u2) * x0 + u2 * x1), a * ((1. - u2) * y0 + u2 * y1))
+            }
+            Aperture::Mask(mask) => mask.sample(sampler.get_2d()),
+        }
+    }
+
+    /// Whether a point lies inside the aperture
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        match self {
+            Aperture::Circular => x * x + y * y <= 1.,
+            Aperture::Polygon { blades, rotation } => {
+                let n = *blades as f64;
+                // Distance to each edge along its normal, compared with the apothem
+                let apothem = (PI / n).cos();
+                (0..*blades).all(|k| {
+                    let angle = rotation + (2. * k as f64 + 1.) * PI / n;
+                    x * angle.cos() + y * angle.sin() <= apothem
+                })
+            }
+            Aperture::Mask(mask) => mask.contains(x, y),
+        }
+    }
+}
+
+fn polygon_vertex(k: f64, n: f64, rotation: f64) -> (f64, f64) {
+    let angle = rotation + 2. * PI * k / n;
+    (angle.cos(), angle.sin())
+}
+
+/// Aperture opening image covering the square [-1, 1] x [-1, 1]
+///
+/// Pixels at least half bright are open and let all light through, darker
+/// ones block it; points are sampled uniformly over the open pixels.
+pub struct ApertureMask {
+    width: u32,
+    height: u32,
+    open: Vec<bool>,
+    /// Cumulative distribution over pixels in row-major order
+    cdf: Vec<f64>,
+}
+
+impl ApertureMask {
+    /// Fails if no pixel is open
+    pub fn from_image(image: &GrayImage) -> Result<Self, String> {
+        let (width, height) = image.dimensions();
+        let open: Vec<bool> = image.pixels().map(|p| p[0] >= 128).collect();
+        let mut total = 0.;
+        let mut cdf: Vec<f64> = open.iter().map(|&o| { total += if o { 1. } else { 0. }; total }).collect();
+        if total == 0. {
+            return Err("aperture mask has no pixel at least half bright".to_string());
+        }
+        for c in cdf.iter_mut() {
+            *c /= total;
+        }
+        Ok(Self { width, height, open, cdf })
+    }
+
+    fn sample(&self, (u1, u2): (f64, f64)) -> (f64, f64) {
+        let idx = self.cdf.partition_point(|&c| c <= u1).min(self.cdf.len() - 1);
+        // Reuse the position of u1 within the chosen pixel's interval
+        let lo = if idx == 0 { 0. } else { self.cdf[idx - 1] };
+        let fx = ((u1 - lo) / (self.cdf[idx] - lo)).min(1.);
+        let px = (idx as u32 % self.width) as f64 + fx;
+        let py = (idx as u32 / self.width) as f64 + u2;
+        // Image rows run top to bottom
+        (2. * px / self.width as f64 - 1., 1. - 2. * py / self.height as f64)
+    }
+
+    fn contains(&self, x: f64, y: f64) -> bool {
+        if !(-1. ..1.).contains(&x) || !(-1. ..1.).contains(&y) {
+            return false;
+        }
+        let px = ((x + 1.) / 2. * self.width as f64) as u32;
+        let py = ((1. - y) / 2. * self.height as f64) as u32;
+        self.open[(py.min(self.height - 1) * self.width + px.min(self.width - 1)) as usize]
+    }
+}
+
+/// One refracting surface or the aperture stop of a lens system
+///
+/// Follows the usual lens table convention: elements are listed from the
+/// object side to the image side and all lengths are in millimeters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LensElement {
+    /// Signed radius of curvature; positive when the center of curvature lies
+    /// on the image side, zero for the flat aperture stop
+    pub curvature_radius: f64,
+    /// Distance along the axis to the next surface
+    pub thickness: f64,
+    /// Index of refraction of the medium behind this surface (image side)
+    pub ior: f64,
+    pub aperture_diameter: f64,
+}
+
+impl LensElement {
+    fn is_stop(&self) -> bool {
+        self.curvature_radius == 0.
+    }
+}
+
+/// Tabulated multi-element lens traced through its spherical surfaces
+///
+/// Lens space has the optical axis along z, the vertex of the rear surface
+/// at z = 0 and the scene towards +z.
+#[derive(Debug, Clone)]
+pub struct LensSystem {
+    elements: Vec<LensElement>,
+    /// Axial position of each surface vertex
+    positions: Vec<f64>,
+}
+
+impl LensSystem {
+    pub fn new(elements: Vec<LensElement>) -> Self {
+        assert!(!elements.is_empty(), "lens system has no elements");
+        let mut positions = vec![0.; elements.len()];
+        for i in (0..elements.len() - 1).rev() {
+            positions[i] = positions[i + 1] + elements[i].thickness;
+        }
+        Self { elements, positions }
+    }
+
+    /// Parses a lens table with one element per line: curvature radius,
+    /// thickness, index of refraction and aperture diameter
+    ///
+    /// Blank lines and lines starting with `#` are ignored. An index of
+    /// refraction of 0 stands for air, as in many published tables.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut elements = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values = line
+                .split_whitespace()
+                .map(|v| v.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|e| format!("line {}: {}", n + 1, e))?;
+            if values.len() != 4 {
+                return Err(format!("line {}: expected 4 values, found {}", n + 1, values.len()));
+            }
+            elements.push(LensElement {
+                curvature_radius: values[0],
+                thickness: values[1],
+                ior: if values[2] == 0. { 1. } else { values[2] },
+                aperture_diameter: values[3],
+            });
+        }
+        if elements.is_empty() {
+            return Err("lens table has no elements".to_string());
+        }
+        Ok(Self::new(elements))
+    }
+
+    /// 50mm f/2 double Gauss design
+    pub fn double_gauss_50mm() -> Self {
+        Self::parse(DOUBLE_GAUSS_50MM).unwrap()
+    }
+
+    pub fn elements(&self) -> &[LensElement] {
+        &self.elements
+    }
+    fn rear(&self) -> &LensElement {
+        self.elements.last().unwrap()
+    }
+    /// Axial position of the front surface vertex
+    fn front_position(&self) -> f64 {
+        self.positions[0]
+    }
+
+    /// Traces a lens-space ray through all surfaces, towards the scene if
+    /// `from_film`, else towards the film. Returns `None` if it is blocked.
+    pub fn trace(&self, ray: &Ray, from_film: bool, aperture: &Aperture, stop_scale: f64) -> Option<Ray> {
+        let mut ray = Ray::new(ray.origin, ray.direction.unit());
+        let n = self.elements.len();
+        for step in 0..n {
+            let i = if from_film { n - 1 - step } else { step };
+            let element = &self.elements[i];
+            let z = self.positions[i];
+            let point = if element.is_stop() {
+                let t = (z - ray.origin.z()) / ray.direction.z();
+                if t.is_nan() || t <= 0. {
+                    return None;
+                }
+                let p = ray.at(t);
+                let r = stop_scale * element.aperture_diameter / 2.;
+                if !aperture.contains(p.x() / r, p.y() / r) {
+                    return None;
+                }
+                p
+            } else {
+                let center = Point3::new(0., 0., z - element.curvature_radius);
+                let p = intersect_surface(&ray, center, element.curvature_radius)?;
+                if p.x() * p.x() + p.y() * p.y() > (element.aperture_diameter / 2.).powi(2) {
+                    return None;
+                }
+                p
+            };
+            if !element.is_stop() {
+                // Media on either side of the surface
+                let image_side = element.ior;
+                let object_side = if i == 0 { 1. } else { self.elements[i - 1].ior };
+                let (eta_i, eta_t) = if from_film { (image_side, object_side) } else { (object_side, image_side) };
+                let center = Point3::new(0., 0., z - element.curvature_radius);
+                let mut normal = (point - center).unit();
+                if normal.dot(ray.direction) > 0. {
+                    normal = -normal;
+                }
+                ray = Ray::new(point, refract_or_block(ray.direction, normal, eta_i / eta_t)?);
+            } else {
+                ray = Ray::new(point, ray.direction);
+            }
+        }
+        Some(ray)
+    }
+
+    /// Distance from the rear vertex to the film that focuses objects at
+    /// `focus_distance` millimeters in front of the front vertex
+    ///
+    /// Traces a paraxial ray from the on-axis object point and finds where it
+    /// crosses the axis behind the lens.
+    pub fn film_distance(&self, focus_distance: f64) -> Result<f64, String> {
+        let object = Point3::new(0., 0., self.front_position() + focus_distance);
+        let height = 0.01 * self.elements[0].aperture_diameter / 2.;
+        let target = Point3::new(height, 0., self.front_position());
+        let ray = Ray::new(object, target - object);
+        let out = self
+            .trace(&ray, false, &Aperture::Circular, 1.)
+            .ok_or_else(|| "paraxial ray blocked by the lens".to_string())?;
+        if out.direction.x() >= 0. {
+            return Err(format!("lens cannot focus at {} mm", focus_distance));
+        }
+        let t = -out.origin.x() / out.direction.x();
+        let z = out.origin.z() + t * out.direction.z();
+        if z >= 0. {
+            return Err(format!("lens cannot focus at {} mm", focus_distance));
+        }
+        Ok(-z)
+    }
+}
+
+/// Hit point on the spherical cap whose vertex is at `center + radius` along z
+fn intersect_surface(ray: &Ray, center: Point3, radius: f64) -> Option<Point3> {
+    let oc = ray.origin - center;
+    let half_b = oc.dot(ray.direction);
+    let c = oc.length_squared() - radius * radius;
+    let discriminant = half_b * half_b - c;
+    if discriminant < 0. {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    // Of the two intersections, keep the one on the same side of the center as the vertex
+    [-half_b - root, -half_b + root]
+        .iter()
+        .filter(|&&t| t > 0.)
+        .map(|&t| ray.at(t))
+        .find(|p| (p.z() - center.z()) * radius > 0.)
+}
+
+/// Refracts a unit direction, or returns `None` on total internal reflection
+fn refract_or_block(direction: Vec3, normal: Vec3, eta_ratio: f64) -> Option<Vec3> {
+    let cos_i = (-direction.dot(normal)).min(1.);
+    let sin2_t = eta_ratio * eta_ratio * (1. - cos_i * cos_i);
+    if sin2_t > 1. {
+        return None;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    Some(eta_ratio * direction + (eta_ratio * cos_i - cos_t) * normal)
+}
+
+const DOUBLE_GAUSS_50MM: &str = "
+# radius   thickness  ior    aperture
+29.475     3.76       1.67   25.2
+84.83      0.12       1      25.2
+19.275     4.025      1.67   23
+40.77      3.275      1.699  23
+12.75      5.705      1      18
+0          4.5        1      17.1
+-14.495    1.18       1.603  17
+40.77      6.065      1.658  20
+-20.385    0.19       1      20
+437.065    3.22       1.717  20
+-39.73     0          1      20
+";
+
+/// Camera that images the scene through a tabulated lens system onto a film
+///
+/// Rays start at a point on the film and aim at a point on the rear element;
+/// rays blocked by element rims or the aperture stop give vignetting,
+/// including cat-eye shaped bokeh towards the image corners.
+pub struct LensSystemCamera {
+    lens: LensSystem,
+    aperture: Aperture,
+    /// Scale applied to the aperture stop diameter, below 1 to stop down
+    stop_scale: f64,
+    film_distance: f64,
+    film_width: f64,
+    film_height: f64,
+    units_per_mm: f64,
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl LensSystemCamera {
+    /// `film_diagonal` is in millimeters, `focus_distance` in scene units and
+    /// `units_per_mm` converts lens dimensions to scene units
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(look_from: Point3,
+               look_at: Point3,
+               vup: Vec3,
+               lens: LensSystem,
+               film_diagonal: f64,
+               aspect_ratio: f64,
+               focus_distance: f64,
+               units_per_mm: f64) -> Result<Self, String> {
+        let film_distance = lens.film_distance(focus_distance / units_per_mm)?;
+        let film_height = film_diagonal / (1. + aspect_ratio * aspect_ratio).sqrt();
+        let w = (look_from - look_at).unit();
+        let u = vup.cross(w).unit();
+        let v = w.cross(u);
+        Ok(Self {
+            lens,
+            aperture: Aperture::Circular,
+            stop_scale: 1.,
+            film_distance,
+            film_width: aspect_ratio * film_height,
+            film_height,
+            units_per_mm,
+            origin: look_from,
+            u,
+            v,
+            w,
+        })
+    }
+    pub fn with_aperture(mut self, aperture: Aperture) -> Self {
+        self.aperture = aperture;
+        self
+    }
+    /// Narrows the aperture stop to `scale` times its tabulated diameter
+    pub fn with_stop_scale(mut self, scale: f64) -> Self {
+        self.stop_scale = scale;
+        self
+    }
+    pub fn film_distance(&self) -> f64 {
+        self.film_distance
+    }
+
+    fn to_world(&self, p: Vec3) -> Vec3 {
+        p.x() * self.u + p.y() * self.v - p.z() * self.w
+    }
+}
+
+impl CameraModel for LensSystemCamera {
+    /// Returns a ray along the optical axis when the sampled ray is blocked
+    fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Ray {
+        self.try_get_ray(s, t, sampler).unwrap_or_else(|| Ray::new(self.origin, -self.w))
+    }
+
+    fn try_get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        // The lens inverts the image, so the film is flipped to keep it upright
+        let film_point = Point3::new(-(s - 0.5) * self.film_width, -(t - 0.5) * self.film_height, -self.film_distance);
+        sampler.set_dimension(LENS_DIMENSION);
+        let (u1, u2) = sampler.get_2d();
+        let r = self.lens.rear().aperture_diameter / 2. * u1.sqrt();
+        let phi = 2. * PI * u2;
+        let rear_point = Point3::new(r * phi.cos(), r * phi.sin(), 0.);
+        let out = self.lens.trace(&Ray::new(film_point, rear_point - film_point), true, &self.aperture, self.stop_scale)?;
+        // Place the front vertex at the camera position
+        let origin = Point3::new(out.origin.x(), out.origin.y(), out.origin.z() - self.lens.front_position());
+        Some(Ray::new(self.origin + self.units_per_mm * self.to_world(origin), self.to_world(out.direction)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IndependentSampler;
+    #[test]
+    fn test_polygon_samples_inside() {
+        let aperture = Aperture::polygon(6);
+        let mut sampler = IndependentSampler::new(1);
+        for i in 0..1000 {
+            sampler.start_pixel_sample(0, 0, i);
+            let (x, y) = aperture.sample(&mut sampler);
+            assert!(aperture.contains(x * 0.999, y * 0.999));
+        }
+        assert!(aperture.contains(0.99, 0.));
+        assert!(!aperture.contains(0., 0.99));
+    }
+    #[test]
+    fn test_mask_samples_bright_region() {
+        // Only the right half of the mask is open
+        let image = GrayImage::from_fn(8, 8, |x, _| image::Luma([if x >= 4 { 255 } else { 0 }]));
+        let aperture = Aperture::Mask(Arc::new(ApertureMask::from_image(&image).unwrap()));
+        let mut sampler = IndependentSampler::new(2);
+        for i in 0..200 {
+            sampler.start_pixel_sample(0, 0, i);
+            let (x, y) = aperture.sample(&mut sampler);
+            assert!(x >= 0. && aperture.contains(x, y));
+        }
+        // Dim pixels are closed, so they are never sampled
+        let dim = GrayImage::from_fn(8, 8, |x, _| image::Luma([if x >= 4 { 255 } else { 100 }]));
+        let aperture = Aperture::Mask(Arc::new(ApertureMask::from_image(&dim).unwrap()));
+        for i in 0..200 {
+            sampler.start_pixel_sample(0, 0, i);
+            let (x, y) = aperture.sample(&mut sampler);
+            assert!(x >= 0. && aperture.contains(x, y));
+        }
+        assert!(ApertureMask::from_image(&GrayImage::new(4, 4)).is_err());
+    }
+    #[test]
+    fn test_double_gauss_focus() {
+        let lens = LensSystem::double_gauss_50mm();
+        // Focal length of roughly 50mm: focusing at infinity puts the film
+        // about a back focal length behind the rear element
+        let far = lens.film_distance(1e7).unwrap();
+        let near = lens.film_distance(1000.).unwrap();
+        assert!(far > 20. && far < 50., "{}", far);
+        assert!(near > far);
+    }
+    #[test]
+    fn test_axial_ray_passes_straight() {
+        let lens = LensSystem::double_gauss_50mm();
+        let ray = Ray::new(Point3::new(0., 0., -40.), Vec3::new(0., 0., 1.));
+        let out = lens.trace(&ray, true, &Aperture::Circular, 1.).unwrap();
+        assert!(out.direction.x().abs() < 1e-9 && out.direction.y().abs() < 1e-9);
+    }
+}
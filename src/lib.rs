@@ -1,8 +1,23 @@
+mod adaptive;
+mod animation;
+mod aov;
 mod camera;
+mod checkpoint;
+mod denoise;
+mod control;
+mod film;
 mod hittable;
+mod lens;
+mod light;
 pub mod materials;
+mod medium;
 mod ray;
+mod sampler;
+mod sky;
 mod sphere;
+mod tiles;
+mod tonemap;
+mod transform;
 mod utils;
 mod vec3;
 pub mod renderers;
@@ -18,9 +33,24 @@ pub fn point3(x: f64, y: f64, z: f64) -> Point3 {
     Point3::new(x, y, z)
 }
 
+pub use adaptive::*;
+pub use animation::*;
+pub use aov::*;
 pub use camera::*;
+pub use checkpoint::*;
+pub use denoise::*;
+pub use control::*;
+pub use film::*;
 pub use hittable::*;
+pub use lens::*;
+pub use light::*;
 pub use materials::*;
+pub use medium::*;
 pub use ray::*;
+pub use sampler::*;
+pub use sky::*;
 pub use sphere::*;
+pub use tiles::*;
+pub use tonemap::*;
+pub use transform::*;
 pub use utils::*;
@@ -0,0 +1,377 @@
+use crate::{clamp, random_unit_vector, Color, Point3, Sampler, Vec3};
+use std::f64::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Light reaching a point from one light source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightSample {
+    /// Unit direction from the lit point towards the light
+    pub direction: Vec3,
+    /// Distance to the light, infinite for directional lights
+    pub distance: f64,
+    /// Irradiance on a surface facing the light
+    pub irradiance: Color,
+}
+
+/// Analytic light located at a single point or direction
+///
+/// Delta lights cannot be hit by rays; they are only found by sampling them
+/// directly with shadow rays from each shaded point.
+pub trait Light {
+    /// Light arriving at `point`, or `None` if the light does not shine on it
+    fn sample(&self, point: Point3) -> Option<LightSample>;
+
+    /// Radiance seen by a ray leaving the scene in `direction`, for distant
+    /// lights with a visible extent
+    fn emitted(&self, _direction: Vec3) -> Color {
+        Color::default()
+    }
+
+    /// Starts a light path with a ray leaving the light, for bidirectional
+    /// methods; `None` for lights that cannot start one, such as directional
+    /// lights
+    fn sample_emission(&self, _sampler: &mut dyn Sampler) -> Option<Emission> {
+        None
+    }
+
+    /// Density per unit solid angle with which `sample_emission` picks the
+    /// unit direction `direction`
+    fn emission_pdf(&self, _direction: Vec3) -> f64 {
+        0.
+    }
+}
+
+/// Ray leaving a light
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Emission {
+    pub origin: Point3,
+    /// Unit direction of the ray
+    pub direction: Vec3,
+    /// Radiant intensity of the light in `direction`
+    pub intensity: Color,
+    /// Density per unit solid angle of `direction`
+    pub pdf: f64,
+}
+
+/// Light emitted equally in all directions from a point
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointLight {
+    pub position: Point3,
+    /// Radiant intensity; irradiance falls off with the squared distance
+    pub intensity: Color,
+}
+
+impl PointLight {
+    pub fn new(position: Point3, intensity: Color) -> Self {
+        Self { position, intensity }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, point: Point3) -> Option<LightSample> {
+        inverse_square(self.position, point, self.intensity)
+    }
+    fn sample_emission(&self, sampler: &mut dyn Sampler) -> Option<Emission> {
+        let direction = random_unit_vector(sampler);
+        Some(Emission { origin: self.position, direction, intensity: self.intensity, pdf: self.emission_pdf(direction) })
+    }
+    fn emission_pdf(&self, _direction: Vec3) -> f64 {
+        1. / (4. * PI)
+    }
+}
+
+/// Relative intensity of a light by angle from its axis, as in IES photometric data
+///
+/// Values are interpolated linearly between the tabulated angles and are zero
+/// beyond the last one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntensityProfile {
+    angles: Vec<f64>,
+    values: Vec<f64>,
+}
+
+impl IntensityProfile {
+    /// Profile from (angle in degrees, relative intensity) pairs in increasing angle order
+    pub fn new(table: &[(f64, f64)]) -> Result<Self, String> {
+        if table.is_empty() {
+            return Err("intensity profile has no entries".to_string());
+        }
+        if table.windows(2).any(|w| w[1].0 <= w[0].0) {
+            return Err("intensity profile angles do not increase".to_string());
+        }
+        if let Some((angle, value)) = table.iter().find(|(_, v)| *v < 0.) {
+            return Err(format!("negative intensity {} at {} degrees", value, angle));
+        }
+        Ok(Self {
+            angles: table.iter().map(|e| e.0).collect(),
+            values: table.iter().map(|e| e.1).collect(),
+        })
+    }
+
+    /// Reads a profile from lines of angle and relative intensity; lines
+    /// starting with '#' are comments
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut table = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values = parse_values(line).map_err(|e| format!("line {}: {}", n + 1, e))?;
+            if values.len() != 2 {
+                return Err(format!("line {}: expected 2 values, found {}", n + 1, values.len()));
+            }
+            table.push((values[0], values[1]));
+        }
+        Self::new(&table)
+    }
+
+    /// Relative intensity at `angle` degrees from the axis
+    pub fn at(&self, angle: f64) -> f64 {
+        let i = self.angles.partition_point(|a| *a <= angle);
+        if i == 0 {
+            return self.values[0];
+        }
+        if i == self.angles.len() {
+            return if angle == self.angles[i - 1] { self.values[i - 1] } else { 0. };
+        }
+        let s = (angle - self.angles[i - 1]) / (self.angles[i] - self.angles[i - 1]);
+        self.values[i - 1] + s * (self.values[i] - self.values[i - 1])
+    }
+}
+
+/// Point light restricted to a cone
+///
+/// Intensity is full inside `inner_angle` and falls off smoothly to zero at
+/// `outer_angle`, both measured from the axis in degrees. An optional profile
+/// scales the intensity further by angle.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpotLight {
+    pub position: Point3,
+    direction: Vec3,
+    pub intensity: Color,
+    cos_inner: f64,
+    cos_outer: f64,
+    profile: Option<Arc<IntensityProfile>>,
+}
+
+impl SpotLight {
+    pub fn new(position: Point3, target: Point3, intensity: Color, inner_angle: f64, outer_angle: f64) -> Self {
+        let outer_angle = outer_angle.max(inner_angle);
+        Self {
+            position,
+            direction: (target - position).unit(),
+            intensity,
+            cos_inner: inner_angle.to_radians().cos(),
+            cos_outer: outer_angle.to_radians().cos(),
+            profile: None,
+        }
+    }
+    pub fn with_profile(mut self, profile: Arc<IntensityProfile>) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Fraction of the intensity emitted in the unit direction `d`
+    fn falloff(&self, d: Vec3) -> f64 {
+        let cos_theta = self.direction.dot(d);
+        let cone = if cos_theta >= self.cos_inner {
+            1.
+        } else if self.cos_inner > self.cos_outer {
+            let s = clamp((cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer), 0., 1.);
+            s * s * (3. - 2. * s)
+        } else {
+            0.
+        };
+        match &self.profile {
+            Some(profile) if cone > 0. => cone * profile.at(clamp(cos_theta, -1., 1.).acos().to_degrees()),
+            _ => cone,
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, point: Point3) -> Option<LightSample> {
+        let falloff = self.falloff((point - self.position).unit());
+        if falloff <= 0. {
+            return None;
+        }
+        inverse_square(self.position, point, falloff * self.intensity)
+    }
+    /// Directions are spread uniformly over the outer cone
+    fn sample_emission(&self, sampler: &mut dyn Sampler) -> Option<Emission> {
+        if self.cos_outer >= 1. {
+            return None;
+        }
+        let (u1, u2) = sampler.get_2d();
+        let cos_theta = 1. - u1 * (1. - self.cos_outer);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * u2;
+        // Any pair of axes perpendicular to the spot direction
+        let a = if self.direction.x().abs() > 0.9 { Vec3::new(0., 1., 0.) } else { Vec3::new(1., 0., 0.) };
+        let b1 = self.direction.cross(a).unit();
+        let b2 = self.direction.cross(b1);
+        let direction = sin_theta * phi.cos() * b1 + sin_theta * phi.sin() * b2 + cos_theta * self.direction;
+        let intensity = self.falloff(direction) * self.intensity;
+        Some(Emission { origin: self.position, direction, intensity, pdf: self.emission_pdf(direction) })
+    }
+    fn emission_pdf(&self, direction: Vec3) -> f64 {
+        if self.direction.dot(direction) >= self.cos_outer && self.cos_outer < 1. {
+            1. / (2. * PI * (1. - self.cos_outer))
+        } else {
+            0.
+        }
+    }
+}
+
+/// Parallel light from a very distant source such as the sun
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DirectionalLight {
+    /// Unit direction the light travels in
+    direction: Vec3,
+    /// Irradiance on a surface facing the light
+    pub irradiance: Color,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Color) -> Self {
+        Self { direction: direction.unit(), irradiance }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _point: Point3) -> Option<LightSample> {
+        Some(LightSample { direction: -self.direction, distance: f64::INFINITY, irradiance: self.irradiance })
+    }
+}
+
+/// Distant light of small angular size, such as the sun disk
+///
+/// It lights the scene like a directional light with the irradiance of the
+/// whole disk, and is visible to camera and specular rays.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SunLight {
+    /// Unit direction towards the center of the disk
+    direction: Vec3,
+    pub radiance: Color,
+    cos_radius: f64,
+    solid_angle: f64,
+}
+
+impl SunLight {
+    /// Disk of `angular_radius` degrees in `direction` from the scene
+    pub fn new(direction: Vec3, radiance: Color, angular_radius: f64) -> Self {
+        let cos_radius = angular_radius.to_radians().cos();
+        Self { direction: direction.unit(), radiance, cos_radius, solid_angle: 2. * std::f64::consts::PI * (1. - cos_radius) }
+    }
+}
+
+impl Light for SunLight {
+    fn sample(&self, _point: Point3) -> Option<LightSample> {
+        Some(LightSample { direction: self.direction, distance: f64::INFINITY, irradiance: self.solid_angle * self.radiance })
+    }
+    fn emitted(&self, direction: Vec3) -> Color {
+        if direction.unit().dot(self.direction) >= self.cos_radius { self.radiance } else { Color::default() }
+    }
+}
+
+fn inverse_square(position: Point3, point: Point3, intensity: Color) -> Option<LightSample> {
+    let to_light = position - point;
+    let distance_squared = to_light.length_squared();
+    if distance_squared == 0. {
+        return None;
+    }
+    let distance = distance_squared.sqrt();
+    Some(LightSample { direction: to_light / distance, distance, irradiance: intensity / distance_squared })
+}
+
+fn parse_values(text: &str) -> Result<Vec<f64>, String> {
+    text.split_whitespace()
+        .map(|v| v.parse::<f64>().map_err(|e| format!("invalid number '{}': {}", v, e)))
+        .collect()
+}
+
+/// Reads a light setup, one light per line:
+///
+/// ```text
+/// point x y z r g b
+/// spot x y z target_x target_y target_z r g b inner_angle outer_angle [profile]
+/// directional dx dy dz r g b
+/// ```
+///
+/// Spot light profiles are read with `IntensityProfile::parse` from files
+/// relative to the setup file. Lines starting with '#' are comments.
+pub fn load_lights(path: &Path) -> Result<Vec<Arc<dyn Light + Sync + Send>>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut lights: Vec<Arc<dyn Light + Sync + Send>> = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let err = |e: String| format!("{} line {}: {}", path.display(), n + 1, e);
+        let mut words = line.split_whitespace();
+        let kind = words.next().unwrap();
+        let rest: Vec<&str> = words.collect();
+        let (numbers, profile) = match (kind, rest.len()) {
+            ("spot", 13) => (&rest[..12], Some(rest[12])),
+            _ => (&rest[..], None),
+        };
+        let v = parse_values(&numbers.join(" ")).map_err(err)?;
+        let vec = |i: usize| Vec3::new(v[i], v[i + 1], v[i + 2]);
+        let expected = match kind {
+            "point" | "directional" => 6,
+            "spot" => 11,
+            _ => return Err(err(format!("unknown light type '{}'", kind))),
+        };
+        if v.len() != expected {
+            return Err(err(format!("expected {} values for a {} light, found {}", expected, kind, v.len())));
+        }
+        lights.push(match kind {
+            "point" => Arc::new(PointLight::new(vec(0), vec(3))),
+            "directional" => Arc::new(DirectionalLight::new(vec(0), vec(3))),
+            _ => {
+                let mut spot = SpotLight::new(vec(0), vec(3), vec(6), v[9], v[10]);
+                if let Some(file) = profile {
+                    let profile_path = dir.join(file);
+                    let text = std::fs::read_to_string(&profile_path)
+                        .map_err(|e| err(format!("{}: {}", profile_path.display(), e)))?;
+                    spot = spot.with_profile(Arc::new(IntensityProfile::parse(&text).map_err(err)?));
+                }
+                Arc::new(spot)
+            }
+        });
+    }
+    Ok(lights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_point_light_inverse_square() {
+        let light = PointLight::new(Point3::new(0., 2., 0.), Color::new(8., 8., 8.));
+        let sample = light.sample(Point3::default()).unwrap();
+        assert_eq!(sample.direction, Vec3::new(0., 1., 0.));
+        assert_eq!(sample.distance, 2.);
+        assert_eq!(sample.irradiance, Color::new(2., 2., 2.));
+    }
+    #[test]
+    fn test_spot_cone_falloff() {
+        let spot = SpotLight::new(Point3::new(0., 1., 0.), Point3::default(), Color::new(1., 1., 1.), 10., 30.);
+        let at = |x: f64| spot.sample(Point3::new(x, 0., 0.)).map_or(0., |s| s.irradiance.x() * (1. + x * x));
+        assert_eq!(at(0.), 1.);
+        let partial = at(20f64.to_radians().tan());
+        assert!(partial > 0. && partial < 1.);
+        assert_eq!(at(1.), 0.);
+    }
+    #[test]
+    fn test_intensity_profile() {
+        let profile = IntensityProfile::parse("# angle intensity\n0 1\n20 0.5\n40 0\n").unwrap();
+        assert_eq!(profile.at(10.), 0.75);
+        assert_eq!(profile.at(60.), 0.);
+        assert!(IntensityProfile::parse("10 1\n5 1").is_err());
+    }
+}
@@ -1,12 +1,50 @@
-use crate::{Ray, HitRecord, Color, random_unit_vector};
+use crate::{Ray, HitRecord, Color, Vec3, Sampler, Medium, random_unit_vector};
 use crate::utils::{reflect, refract, schlick, clamp, fmin};
-use rand::prelude::*;
 
 pub trait Material {
     /**
      * Returns a ray if there is a scattered ray
      */
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)>;
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<(Ray, Color)>;
+
+    /// Surface color used for the albedo output buffer
+    fn albedo(&self) -> Color {
+        Color::new(1., 1., 1.)
+    }
+
+    /// Fraction of the light arriving from the unit direction `light_dir`
+    /// that is scattered back along the incoming ray, per unit solid angle and
+    /// including the cosine at the surface. Used to shade with lights directly;
+    /// perfectly specular materials return black.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _light_dir: Vec3) -> Color {
+        Color::default()
+    }
+
+    /// Density per unit solid angle with which `scatter` picks the unit
+    /// direction `direction`; 0 for materials that scatter into discrete
+    /// directions, whose `eval` is black
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vec3) -> f64 {
+        0.
+    }
+
+    /// Whether `eval` accounts for all the light this material reflects from
+    /// the lights, so rays it scatters must not see the lights again
+    fn shades_lights_directly(&self) -> bool {
+        false
+    }
+
+    /// Rays of the ideal mirror reflection and refraction at the surface, with
+    /// the fraction of light each carries, for tracing without random
+    /// sampling. Roughness is ignored; diffuse materials have none.
+    fn specular_rays(&self, _r_in: &Ray, _rec: &HitRecord) -> Vec<(Ray, Color)> {
+        Vec::new()
+    }
+
+    /// Medium filling objects made of this material, seen by rays transmitted
+    /// through their surface; `None` for a vacuum
+    fn interior(&self) -> Option<&Medium> {
+        None
+    }
 }
 pub struct Lambertian {
     albedo: Color
@@ -17,12 +55,26 @@ impl Lambertian {
     }
 }
 impl Material for Lambertian {
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)>
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<(Ray, Color)>
     {
-        let scatter_direction = rec.normal + random_unit_vector();
+        let scatter_direction = rec.normal + random_unit_vector(sampler);
         let scattered = Ray::new(rec.point, scatter_direction);
         Some((scattered, self.albedo))
     }
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, light_dir: Vec3) -> Color {
+        let cos_theta = rec.normal.dot(light_dir);
+        if cos_theta > 0. { cos_theta / std::f64::consts::PI * self.albedo } else { Color::default() }
+    }
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        // Cosine distributed about the normal
+        rec.normal.dot(direction).max(0.) / std::f64::consts::PI
+    }
+    fn shades_lights_directly(&self) -> bool {
+        true
+    }
 }
 pub struct Metal {
     albedo: Color,
@@ -38,10 +90,10 @@ impl Metal {
     }
 }
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)>
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<(Ray, Color)>
     {
         let reflected = reflect(r_in.direction.unit(), rec.normal);
-        let scattered = Ray::new(rec.point, reflected + self.fuzz*random_unit_vector());
+        let scattered = Ray::new(rec.point, reflected + self.fuzz*random_unit_vector(sampler));
         let is_scattered = scattered.direction.dot(rec.normal) > 0.;
         if is_scattered
         {
@@ -50,20 +102,35 @@ impl Material for Metal {
             None
         }
     }
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+    fn specular_rays(&self, r_in: &Ray, rec: &HitRecord) -> Vec<(Ray, Color)> {
+        let reflected = reflect(r_in.direction.unit(), rec.normal);
+        vec![(Ray::new(rec.point, reflected), self.albedo)]
+    }
 }
 
 pub struct Dielectric {
     ref_idx: f64,
+    medium: Option<Medium>,
 }
 impl Dielectric {
     pub fn new(ref_idx: f64) -> Self {
         Self {
-            ref_idx
+            ref_idx,
+            medium: None,
         }
     }
+    /// Fills the inside with an absorbing or scattering medium, as in colored
+    /// glass or liquids
+    pub fn with_medium(mut self, medium: Medium) -> Self {
+        self.medium = Some(medium);
+        self
+    }
 }
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)>
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<(Ray, Color)>
     {
         let attenuation = Color::new(1.0, 1.0, 1.0);
 
@@ -84,8 +151,7 @@ impl Material for Dielectric {
         }
         // Glancing Reflection
         let reflect_prob = schlick(cos_theta, etai_over_etat);
-        let mut rng = thread_rng();
-        if rng.gen::<f64>() < reflect_prob
+        if sampler.get_1d() < reflect_prob
         {
             let reflected = reflect(ray_unit, rec.normal);
             let scattered = Ray::new(rec.point, reflected);
@@ -96,4 +162,82 @@ impl Material for Dielectric {
         let scattered = Ray::new(rec.point, refracted);
         Some((scattered, attenuation))
     }
+    fn specular_rays(&self, r_in: &Ray, rec: &HitRecord) -> Vec<(Ray, Color)> {
+        let etai_over_etat = if rec.is_front_face { 1.0 / self.ref_idx } else { self.ref_idx };
+        let ray_unit = r_in.direction.unit();
+        let cos_theta = fmin(-ray_unit.dot(rec.normal), 1.0);
+        let sin_theta = (1.0 - cos_theta*cos_theta).sqrt();
+        let reflected = Ray::new(rec.point, reflect(ray_unit, rec.normal));
+        if etai_over_etat * sin_theta > 1.0 {
+            return vec![(reflected, Color::new(1., 1., 1.))];
+        }
+        let r = schlick(cos_theta, etai_over_etat);
+        let refracted = Ray::new(rec.point, refract(ray_unit, rec.normal, etai_over_etat));
+        vec![(reflected, Color::new(r, r, r)), (refracted, Color::new(1. - r, 1. - r, 1. - r))]
+    }
+    fn interior(&self) -> Option<&Medium> {
+        self.medium.as_ref()
+    }
+}
+
+/// Translucent material scattering light below its surface, such as skin,
+/// wax or marble, traced as a random walk through its inside
+///
+/// Light arriving at the smooth surface is reflected or refracted like with
+/// `Dielectric`; rays that enter walk through a dense medium and leave as soon
+/// as they reach the surface again, without internal reflection.
+/// `mean_free_path` is the average distance light travels between scattering
+/// events, per channel, and `albedo` the color of thick objects below the
+/// surface reflection. The single-scattering albedo giving that color after
+/// many bounces comes from the fit of Chiang, Kutz and Burley (2016), which
+/// assumes walks leave freely. Objects must be closed.
+pub struct Subsurface {
+    albedo: Color,
+    mean_free_path: Color,
+    surface: Dielectric,
+}
+impl Subsurface {
+    pub fn new(albedo: Color, mean_free_path: Color) -> Self {
+        Self::new_with_ior(albedo, mean_free_path, 1.4)
+    }
+    pub fn new_with_ior(albedo: Color, mean_free_path: Color, ior: f64) -> Self {
+        let single_scattering = |a: f64| {
+            let a = clamp(a, 0., 0.999);
+            1. - (4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt()).powi(2)
+        };
+        let extinction = |d: f64| 1. / d.max(1e-6);
+        let sigma_t = Color::new(extinction(mean_free_path.x()), extinction(mean_free_path.y()), extinction(mean_free_path.z()));
+        let ss = Color::new(single_scattering(albedo.x()), single_scattering(albedo.y()), single_scattering(albedo.z()));
+        let medium = Medium::new(sigma_t - ss * sigma_t, ss * sigma_t);
+        Self {
+            albedo,
+            mean_free_path,
+            surface: Dielectric::new(ior).with_medium(medium),
+        }
+    }
+    pub fn mean_free_path(&self) -> Color {
+        self.mean_free_path
+    }
+}
+impl Material for Subsurface {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<(Ray, Color)> {
+        if rec.is_front_face {
+            self.surface.scatter(r_in, rec, sampler)
+        } else {
+            Some((Ray::new(rec.point, r_in.direction), Color::new(1., 1., 1.)))
+        }
+    }
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+    fn specular_rays(&self, r_in: &Ray, rec: &HitRecord) -> Vec<(Ray, Color)> {
+        if rec.is_front_face {
+            self.surface.specular_rays(r_in, rec)
+        } else {
+            vec![(Ray::new(rec.point, r_in.direction), Color::new(1., 1., 1.))]
+        }
+    }
+    fn interior(&self) -> Option<&Medium> {
+        self.surface.interior()
+    }
 }
\ No newline at end of file
@@ -0,0 +1,134 @@
+use crate::{Color, Ray, Sampler, Vec3};
+use std::f64::consts::PI;
+
+/// Homogeneous participating medium filling the inside of an object
+///
+/// Light travelling a distance d through it keeps exp(-σ d) of its radiance
+/// per channel (Beer–Lambert), where σ is the sum of the absorption and
+/// scattering coefficients; scattered light continues in a direction drawn
+/// from the Henyey–Greenstein phase function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Medium {
+    /// Fraction of the light absorbed per unit distance, per channel
+    pub absorption: Color,
+    /// Fraction of the light scattered per unit distance, per channel
+    pub scattering: Color,
+    /// Mean cosine of the scattering angle, from -1 (back) through 0
+    /// (isotropic) to 1 (forward)
+    pub anisotropy: f64,
+}
+
+impl Medium {
+    pub fn new(absorption: Color, scattering: Color) -> Self {
+        Self { absorption, scattering, anisotropy: 0. }
+    }
+    /// Clear absorbing medium, such as tinted glass, that leaves `color` of
+    /// the light after `distance`
+    pub fn tinted(color: Color, distance: f64) -> Self {
+        let absorption = |c: f64| -c.max(1e-6).ln() / distance;
+        Self::new(Color::new(absorption(color.x()), absorption(color.y()), absorption(color.z())), Color::default())
+    }
+    pub fn with_anisotropy(mut self, anisotropy: f64) -> Self {
+        self.anisotropy = anisotropy.clamp(-0.99, 0.99);
+        self
+    }
+
+    pub fn extinction(&self) -> Color {
+        self.absorption + self.scattering
+    }
+
+    /// Fraction of the light left after `distance`, which may be infinite
+    pub fn transmittance(&self, distance: f64) -> Color {
+        let sigma = self.extinction();
+        let channel = |s: f64| if s > 0. { (-s * distance).exp() } else { 1. };
+        Color::new(channel(sigma.x()), channel(sigma.y()), channel(sigma.z()))
+    }
+
+    /// Samples where light travelling along `r` is first scattered, given the
+    /// `distance` to the surface bounding the medium. Returns the ray leaving
+    /// the scattering point, if it comes before the surface, and the weight of
+    /// the segment. Uses up to three sample dimensions.
+    pub fn sample_interaction(&self, r: &Ray, distance: f64, sampler: &mut dyn Sampler) -> (Option<Ray>, Color) {
+        if self.scattering == Color::default() {
+            return (None, self.transmittance(distance));
+        }
+        // Distances follow the extinction of one channel picked at random, and
+        // are weighted by the average density over the three channels
+        let sigma = self.extinction();
+        let u = 3. * sampler.get_1d();
+        let channel = (u as usize).min(2);
+        let t = if sigma[channel] > 0. { -(1. - (u - channel as f64)).ln() / sigma[channel] } else { f64::INFINITY };
+        if t < distance {
+            let transmittance = self.transmittance(t);
+            let pdf = (sigma * transmittance).dot(Vec3::new(1., 1., 1.)) / 3.;
+            let direction = r.direction.unit();
+            let scattered = Ray::new(r.origin + t * direction, self.sample_direction(direction, sampler));
+            (Some(scattered), self.scattering * transmittance / pdf)
+        } else {
+            let transmittance = self.transmittance(distance);
+            let pdf = transmittance.dot(Vec3::new(1., 1., 1.)) / 3.;
+            (None, if pdf > 0. { transmittance / pdf } else { Color::default() })
+        }
+    }
+
+    /// Samples the unit direction light travelling along the unit `direction`
+    /// scatters into, in proportion to the phase function
+    pub fn sample_direction(&self, direction: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let (u1, u2) = sampler.get_2d();
+        let g = self.anisotropy;
+        let cos_theta = if g.abs() < 1e-3 {
+            1. - 2. * u1
+        } else {
+            let s = (1. - g * g) / (1. + g - 2. * g * u1);
+            (1. + g * g - s * s) / (2. * g)
+        };
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * u2;
+        // Frame around the direction of travel
+        let helper = if direction.x().abs() > 0.9 { Vec3::new(0., 1., 0.) } else { Vec3::new(1., 0., 0.) };
+        let u = direction.cross(helper).unit();
+        let v = direction.cross(u);
+        sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * direction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IndependentSampler, Point3};
+    #[test]
+    fn test_tinted_transmittance() {
+        let medium = Medium::tinted(Color::new(0.5, 0.25, 1.), 2.);
+        let t = medium.transmittance(2.);
+        assert!((t.x() - 0.5).abs() < 1e-12 && (t.y() - 0.25).abs() < 1e-12 && (t.z() - 1.).abs() < 1e-12);
+        assert_eq!(medium.transmittance(f64::INFINITY).z(), 1.);
+    }
+    #[test]
+    fn test_forward_scattering_mean_cosine() {
+        let medium = Medium::new(Color::default(), Color::new(1., 1., 1.)).with_anisotropy(0.6);
+        let mut sampler = IndependentSampler::new(3);
+        let direction = Vec3::new(0., 0., -1.);
+        let n = 20000;
+        let mut sum = 0.;
+        for i in 0..n {
+            sampler.start_pixel_sample(i, 0, 0);
+            sum += medium.sample_direction(direction, &mut sampler).dot(direction);
+        }
+        assert!((sum / n as f64 - 0.6).abs() < 0.02);
+    }
+    #[test]
+    fn test_interaction_weights_average_to_transmittance() {
+        // Without absorption, scattered and passing light together keep all of it
+        let medium = Medium::new(Color::default(), Color::new(0.5, 1., 2.));
+        let r = Ray::new(Point3::default(), Vec3::new(1., 0., 0.));
+        let mut sampler = IndependentSampler::new(5);
+        let n = 20000;
+        let mut total = Color::default();
+        for i in 0..n {
+            sampler.start_pixel_sample(i, 0, 0);
+            total += medium.sample_interaction(&r, 1., &mut sampler).1;
+        }
+        let mean = total / n as f64;
+        assert!((mean.x() - 1.).abs() < 0.05 && (mean.z() - 1.).abs() < 0.05, "{:?}", mean);
+    }
+}
@@ -7,8 +7,8 @@ pub struct Ray {
 impl Ray {
     pub fn new(origin: Point3, direction: Vec3) -> Self {
         Self {
-            origin: origin.clone(),
-            direction: direction.clone(),
+            origin,
+            direction,
         }
     }
     pub fn at(&self, t: f64) -> Point3 {
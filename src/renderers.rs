@@ -1,144 +1,1055 @@
-use rand::prelude::*;
 use image::RgbImage;
-use crate::{Camera, HittableList, color, Color, Ray, Hittable};
-use indicatif::ParallelProgressIterator;
-use indicatif::{ProgressBar, ProgressStyle};
+use crate::{CameraModel, HittableList, clamp, color, Color, Ray, Hittable, ToneMap, Film, Filter, Sampler, SamplerKind, random_unit_vector};
+use crate::{AdaptiveSampling, PixelStatistics, Tile, TileOrder, make_tiles, Checkpoint};
+use crate::{RenderControl, RenderProgress, AovSample, Material, Denoiser, HitRecord};
+use crate::{Medium, BOUNCE_DIMENSION, DIMENSIONS_PER_BOUNCE, MEDIUM_DIMENSION, ROULETTE_DIMENSION};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Instant;
 
-fn ray_color(r: &Ray, world: &HittableList, depth: i32) -> Color {
-    // If we've exceeded the ray bounce limit, no more light is gathered.
-    if depth <= 0
-    {
-        return color(0., 0., 0.);
+mod bdpt;
+mod photon;
+pub use bdpt::BdptRenderer;
+pub use photon::PhotonMapRenderer;
+
+/// Radiance arriving along `r`, whose hit `first_hit` the caller has found,
+/// following the path one bounce at a time
+///
+/// Light from the lights is added at every surface with shadow rays; rays
+/// scattered from surfaces shaded that way no longer see the lights. Paths
+/// end when they leave the scene, are absorbed, reach `max_depth` bounces or
+/// the bounce limit of their kind, or lose at Russian roulette. Also returns
+/// the number of surfaces the path hit and scattering events in media.
+///
+/// Paths keep track of the objects they are inside of, entering them when
+/// transmitted through a front face and leaving through a back face, and are
+/// absorbed and scattered by the medium of the innermost one; the camera is
+/// assumed to be outside of all objects. Scattering events in media count
+/// towards the `volume` bounce limit only, as random walks through dense
+/// media such as `Subsurface` materials take many steps.
+fn ray_color(r: &Ray, first_hit: Option<HitRecord>, world: &HittableList, max_depth: i32, settings: &RenderSettings, sampler: &mut dyn Sampler) -> (Color, u32) {
+    let mut radiance = Color::default();
+    let mut throughput = color(1., 1., 1.);
+    let mut ray = Ray::new(r.origin, r.direction);
+    let mut sees_lights = true;
+    let mut bounces = BounceCounts::default();
+    let mut inside = Vec::new();
+    // Surfaces hit, counted against `max_depth`, and scattering events in media
+    let (mut bounce, mut steps) = (0, 0);
+    let mut first_hit = Some(first_hit);
+    while bounce < max_depth.max(0) as u32 {
+        let hit = first_hit.take().unwrap_or_else(|| world.hit(&ray, 0.001, f64::INFINITY));
+        let mut scattered_in_medium = None;
+        if let Some(medium) = current_medium(&inside) {
+            let distance = hit.as_ref().map_or(f64::INFINITY, |rec| rec.distance * ray.direction.length());
+            sampler.set_dimension(MEDIUM_DIMENSION + DIMENSIONS_PER_BOUNCE * steps);
+            let (scattered, weight) = medium.sample_interaction(&ray, distance, sampler);
+            throughput = throughput * weight;
+            scattered_in_medium = scattered;
+        }
+        let (scattered, roulette_dimension) = match (scattered_in_medium, hit) {
+            (Some(scattered), _) => {
+                steps += 1;
+                if steps > settings.bounce_limits.volume {
+                    return (radiance, bounce + steps);
+                }
+                // Light reaches points inside media only through their boundary
+                sees_lights = true;
+                (scattered, MEDIUM_DIMENSION + DIMENSIONS_PER_BOUNCE * (steps - 1) + ROULETTE_DIMENSION)
+            }
+            (None, None) => return (radiance + throughput * world.background(&ray, sees_lights), bounce + steps),
+            (None, Some(rec)) => {
+                sampler.start_bounce(bounce);
+                bounce += 1;
+                radiance += throughput * rec.direct_light(&ray, world);
+                let (scattered, attenuation) = match rec.scatter(&ray, sampler) {
+                    Some(scatter) => scatter,
+                    None => return (radiance, bounce + steps),
+                };
+                let kind = BounceKind::of(&rec, &scattered);
+                if !bounces.add(kind, &settings.bounce_limits) {
+                    return (radiance, bounce + steps);
+                }
+                if kind == BounceKind::Transmission {
+                    cross_boundary(&mut inside, &rec);
+                }
+                throughput = throughput * attenuation;
+                sees_lights = !rec.shades_lights_directly();
+                (scattered, BOUNCE_DIMENSION + DIMENSIONS_PER_BOUNCE * (bounce - 1) + ROULETTE_DIMENSION)
+            }
+        };
+        if let Some(roulette) = &settings.russian_roulette {
+            if bounce + steps >= roulette.min_bounces {
+                let survival = roulette.survival_probability(throughput);
+                sampler.set_dimension(roulette_dimension);
+                if sampler.get_1d() >= survival {
+                    return (radiance, bounce + steps);
+                }
+                throughput /= survival;
+            }
+        }
+        ray = scattered;
     }
-    if let Some(rec) = world.hit(r, 0.001, std::f64::INFINITY) 
-    {
-        if let Some((scattered_ray, attenuation)) = rec.scatter(r)
-        {
-            return attenuation * ray_color(&scattered_ray, world, depth -1);
+    // Out of bounces: no more light is gathered
+    (radiance, bounce + steps)
+}
+
+/// Materials of the objects a path is inside of, innermost last
+type Inside = Vec<Arc<dyn Material + Sync + Send>>;
+
+/// Enters or leaves the object hit by `rec` as a path is transmitted through
+/// its surface. Objects are left by removing their innermost entry, so that
+/// nested and overlapping objects are tracked correctly.
+fn cross_boundary(inside: &mut Inside, rec: &HitRecord) {
+    let material = match &rec.material {
+        Some(material) => material,
+        None => return,
+    };
+    if rec.is_front_face {
+        inside.push(material.clone());
+    } else if let Some(index) = inside.iter().rposition(|m| Arc::ptr_eq(m, material)) {
+        inside.remove(index);
+    }
+}
+
+/// Medium filling the innermost object a path is inside of
+fn current_medium(inside: &[Arc<dyn Material + Sync + Send>]) -> Option<&Medium> {
+    inside.last().and_then(|m| m.interior())
+}
+
+/// Quantity computed for each camera ray
+///
+/// Everything except the path tracer is a quick diagnostic view of the scene
+/// and camera, shading only the first surface hit.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Integrator {
+    /// Light transport, see `ray_color`
+    #[default]
+    PathTracer,
+    /// Fraction of the hemisphere above the first hit open for `radius` scene
+    /// units, estimated with one cosine distributed ray per sample
+    AmbientOcclusion { radius: f64 },
+    /// Outward unit normal mapped from [-1, 1] to [0, 1]
+    Normals,
+    /// Cosine between the normal and the direction towards the camera
+    FacingRatio,
+    /// Checkerboard of `checks` squares along each surface coordinate
+    UvChecker { checks: f64 },
+    /// Number of surfaces hit by the path tracer, from blue for none to red
+    /// for `max_depth`
+    BounceHeatmap,
+    /// Noise-free preview, see `whitted_color`
+    Whitted,
+}
+
+impl Integrator {
+    /// Computes the quantity for the camera ray `r`, given its `hit`
+    fn trace(&self, r: &Ray, hit: Option<HitRecord>, world: &HittableList, max_depth: i32, settings: &RenderSettings, sampler: &mut dyn Sampler) -> Color {
+        match (*self, hit) {
+            (Integrator::PathTracer, hit) => ray_color(r, hit, world, max_depth, settings, sampler).0,
+            (Integrator::BounceHeatmap, hit) => {
+                let (_, bounces) = ray_color(r, hit, world, max_depth, settings, sampler);
+                heatmap(f64::from(bounces) / f64::from(max_depth.max(1)))
+            }
+            (Integrator::Whitted, hit) => whitted_color(r, hit, world, max_depth, color(1., 1., 1.), &Vec::new()),
+            // Open sky is unoccluded
+            (Integrator::AmbientOcclusion { .. }, None) => color(1., 1., 1.),
+            (_, None) => Color::default(),
+            (Integrator::AmbientOcclusion { radius }, Some(rec)) => {
+                sampler.start_bounce(0);
+                let direction = rec.normal + random_unit_vector(sampler);
+                // Skip the rare samples canceling out the normal
+                if direction.length_squared() < 1e-12 {
+                    return color(1., 1., 1.);
+                }
+                let direction = direction.unit();
+                if world.occluded(&Ray::new(rec.point, direction), 0.001, radius) {
+                    Color::default()
+                } else {
+                    color(1., 1., 1.)
+                }
+            }
+            (Integrator::Normals, Some(rec)) => {
+                let n = if rec.is_front_face { rec.normal } else { -rec.normal };
+                0.5 * (n + color(1., 1., 1.))
+            }
+            (Integrator::FacingRatio, Some(rec)) => {
+                let facing = rec.normal.dot(-r.direction.unit()).max(0.);
+                color(facing, facing, facing)
+            }
+            (Integrator::UvChecker { checks }, Some(rec)) => {
+                let parity = (rec.u * checks).floor() as i64 + (rec.v * checks).floor() as i64;
+                if parity.rem_euclid(2) == 0 {
+                    color(0.9, 0.9, 0.9)
+                } else {
+                    color(0.1, 0.1, 0.1)
+                }
+            }
+        }
+    }
+}
+
+/// Branches carrying less than this fraction of the camera ray's light are not followed
+const WHITTED_MIN_WEIGHT: f64 = 1e-3;
+
+/// Whitted's recursive ray tracer: light from the lights with hard shadows,
+/// plus mirror reflection and Fresnel weighted refraction followed
+/// recursively, without random sampling. Diffuse surfaces also take the
+/// environment seen along their normal, unshadowed, as ambient light. Media
+/// only attenuate, scattered light being lost. `hit` is the hit of `r`,
+/// `weight` the fraction of the camera ray's light it carries and `inside` the
+/// objects it travels in.
+fn whitted_color(r: &Ray, hit: Option<HitRecord>, world: &HittableList, depth: i32, weight: Color, inside: &Inside) -> Color {
+    if depth <= 0 {
+        return Color::default();
+    }
+    let transmittance = current_medium(inside).map_or(color(1., 1., 1.), |medium| {
+        medium.transmittance(hit.as_ref().map_or(f64::INFINITY, |rec| rec.distance * r.direction.length()))
+    });
+    let rec = match hit {
+        Some(rec) => rec,
+        None => return transmittance * world.background(r, true),
+    };
+    let material = match &rec.material {
+        Some(material) => material,
+        None => return Color::default(),
+    };
+    let mut radiance = rec.direct_light(r, world);
+    if material.shades_lights_directly() {
+        radiance += material.albedo() * world.environment().radiance(rec.normal);
+    }
+    for (ray, attenuation) in material.specular_rays(r, &rec) {
+        let branch = weight * transmittance * attenuation;
+        // Branches past the depth limit would gather nothing
+        if depth <= 1 || branch.x().max(branch.y()).max(branch.z()) < WHITTED_MIN_WEIGHT {
+            continue;
+        }
+        let hit = world.hit(&ray, 0.001, f64::INFINITY);
+        let radiance_in = if ray.direction.dot(rec.normal) < 0. {
+            let mut inside = inside.clone();
+            cross_boundary(&mut inside, &rec);
+            whitted_color(&ray, hit, world, depth - 1, branch, &inside)
+        } else {
+            whitted_color(&ray, hit, world, depth - 1, branch, inside)
+        };
+        radiance += attenuation * radiance_in;
+    }
+    transmittance * radiance
+}
+
+/// Blue to cyan, green, yellow and red as `t` goes from 0 to 1
+fn heatmap(t: f64) -> Color {
+    let t = clamp(t, 0., 1.) * 4.;
+    let ramp = |x: f64| clamp(x, 0., 1.);
+    color(ramp(t - 2.), ramp(if t < 3. { t } else { 4. - t }), ramp(2. - t))
+}
+
+impl FromStr for Integrator {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "path" => Ok(Integrator::PathTracer),
+            "ao" => Ok(Integrator::AmbientOcclusion { radius: 1. }),
+            "normals" => Ok(Integrator::Normals),
+            "facing" => Ok(Integrator::FacingRatio),
+            "uv" => Ok(Integrator::UvChecker { checks: 16. }),
+            "bounces" => Ok(Integrator::BounceHeatmap),
+            "whitted" => Ok(Integrator::Whitted),
+            _ => Err(format!("unknown integrator '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Integrator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Integrator::PathTracer => "path",
+            Integrator::AmbientOcclusion { .. } => "ao",
+            Integrator::Normals => "normals",
+            Integrator::FacingRatio => "facing",
+            Integrator::UvChecker { .. } => "uv",
+            Integrator::BounceHeatmap => "bounces",
+            Integrator::Whitted => "whitted",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BounceKind {
+    Diffuse,
+    Specular,
+    Transmission,
+}
+
+impl BounceKind {
+    /// Classifies a scatter event; rays continuing through the surface are
+    /// transmitted, and materials shaded directly with lights are diffuse
+    fn of(rec: &HitRecord, scattered: &Ray) -> Self {
+        if scattered.direction.dot(rec.normal) < 0. {
+            BounceKind::Transmission
+        } else if rec.shades_lights_directly() {
+            BounceKind::Diffuse
+        } else {
+            BounceKind::Specular
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct BounceCounts {
+    diffuse: u32,
+    specular: u32,
+    transmission: u32,
+}
+
+impl BounceCounts {
+    /// Counts a bounce, returning false if it exceeds the limit of its kind
+    fn add(&mut self, kind: BounceKind, limits: &BounceLimits) -> bool {
+        let (count, limit) = match kind {
+            BounceKind::Diffuse => (&mut self.diffuse, limits.diffuse),
+            BounceKind::Specular => (&mut self.specular, limits.specular),
+            BounceKind::Transmission => (&mut self.transmission, limits.transmission),
+        };
+        *count += 1;
+        *count <= limit
+    }
+}
+
+/// Maximum number of bounces of each kind along a path, on top of the overall
+/// `max_depth` given to the renderer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BounceLimits {
+    pub diffuse: u32,
+    /// Reflections off mirrors, metals and glass
+    pub specular: u32,
+    /// Refractions into or out of glass
+    pub transmission: u32,
+    /// Scattering events inside media, which are not limited by `max_depth`
+    pub volume: u32,
+}
+
+impl Default for BounceLimits {
+    fn default() -> Self {
+        Self { diffuse: u32::MAX, specular: u32::MAX, transmission: u32::MAX, volume: 256 }
+    }
+}
+
+/// Ends paths at random once their throughput gets low, reweighting the ones
+/// that continue so that the image stays unbiased
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RussianRoulette {
+    /// Bounces always traced before the roulette starts
+    pub min_bounces: u32,
+    /// Upper bound on the survival probability, so that even bright paths end
+    pub max_survival: f64,
+}
+
+impl Default for RussianRoulette {
+    fn default() -> Self {
+        Self { min_bounces: 3, max_survival: 0.95 }
+    }
+}
+
+impl RussianRoulette {
+    /// Probability of continuing a path, following its brightest channel
+    fn survival_probability(&self, throughput: Color) -> f64 {
+        throughput.x().max(throughput.y()).max(throughput.z()).min(self.max_survival)
+    }
+}
+
+/// Settings shared by all renderers
+#[derive(Debug, Clone, Default)]
+pub struct RenderSettings {
+    /// Applied when converting accumulated radiance to 8-bit pixels
+    pub tone_map: ToneMap,
+    /// Pixel reconstruction filter used when splatting samples onto the film
+    pub filter: Filter,
+    /// Seed for all random sampling; equal seeds give identical images
+    pub seed: u64,
+    /// Strategy used to generate pixel, lens and scatter samples
+    pub sampler: SamplerKind,
+    /// Stop sampling converged pixels early; `samples_per_pixel` becomes the maximum
+    pub adaptive: Option<AdaptiveSampling>,
+    /// Progress callbacks, cancellation and time budget
+    pub control: RenderControl,
+    /// Denoise the image rendered by `Renderer::render`, guided by first-hit AOVs
+    pub denoiser: Option<Denoiser>,
+    /// Record the first-hit AOVs of the camera samples in the rendered film,
+    /// see `Film::aovs`; always done when denoising
+    pub aovs: bool,
+    /// Per-kind bounce limits of the paths traced
+    pub bounce_limits: BounceLimits,
+    /// Terminate low-contribution paths early; `None` traces every path to its limits
+    pub russian_roulette: Option<RussianRoulette>,
+    /// What the camera samples compute; the bidirectional and photon mapping
+    /// renderers always trace light transport
+    pub integrator: Integrator,
+}
+
+impl RenderSettings {
+    fn records_aovs(&self) -> bool {
+        self.aovs || self.denoiser.is_some()
+    }
+
+    /// Film covering the pixels [x0, x0 + width) x [y0, y0 + height) of the
+    /// image, recording AOVs if requested
+    fn film_region(&self, x0: u32, y0: u32, width: u32, height: u32) -> Film {
+        let film = Film::new_region(x0, y0, width, height, self.filter);
+        if self.records_aovs() {
+            film.with_aovs()
+        } else {
+            film
+        }
+    }
+}
+
+/// Film position and radiance of each sample taken in a pixel
+type PixelSamples = Vec<(f64, f64, Color)>;
+
+/// State shared by the renderers while tracing the camera samples of one render call
+struct RenderJob<'a> {
+    settings: &'a RenderSettings,
+    scene: &'a HittableList,
+    camera: &'a dyn CameraModel,
+    image_width: u32,
+    image_height: u32,
+    /// Number of samples to take per pixel in this call
+    samples_per_pixel: u32,
+    /// Sample index of the first sample, non-zero when adding to earlier passes
+    first_sample: u32,
+    max_depth: i32,
+    progress: &'a RenderProgress<'a>,
+    /// Whether tiles may stop part way when the render is cancelled or out of time
+    interruptible: bool,
+    /// Numbering of the scene's materials, set when AOVs are recorded
+    materials: Option<&'a MaterialIds>,
+}
+
+impl<'a> RenderJob<'a> {
+    fn create_sampler(&self) -> Box<dyn Sampler> {
+        self.settings.sampler.create(self.settings.seed, self.samples_per_pixel)
+    }
+
+    /// Traces sample `index` of pixel (i, j), counting rows from the bottom of
+    /// the image, and returns its film position, radiance and, if recorded,
+    /// the AOVs of the first hit
+    fn trace_sample(&self, sampler: &mut dyn Sampler, i: u32, j: u32, index: u32) -> (f64, f64, Color, Option<AovSample>) {
+        let start = Instant::now();
+        let (x, y, r) = self.camera_ray(sampler, i, j, index);
+        let (sample_color, aov) = match r {
+            Some(r) => {
+                let hit = self.scene.hit(&r, 0.001, f64::INFINITY);
+                let aov = self.aov_sample(hit.as_ref().map(|rec| (&r, rec)));
+                (self.settings.integrator.trace(&r, hit, self.scene, self.max_depth, self.settings, sampler), aov)
+            }
+            None => (Color::default(), self.aov_sample(None)),
+        };
+        self.progress.add_sample_time(start.elapsed());
+        (x, y, sample_color, aov)
+    }
+
+    /// Generates the camera ray of sample `index` of pixel (i, j) and its film position
+    /// The ray is `None` if it was blocked inside the camera.
+    fn camera_ray(&self, sampler: &mut dyn Sampler, i: u32, j: u32, index: u32) -> (f64, f64, Option<Ray>) {
+        sampler.start_pixel_sample(i, j, index);
+        let (dx, dy) = sampler.get_2d();
+        let r = self.camera.film_ray(i as f64 + dx, j as f64 + dy, self.image_width, self.image_height, sampler);
+        // Film rows run top to bottom
+        (i as f64 + dx, (self.image_height - j) as f64 - dy, r)
+    }
+
+    /// AOVs of a camera sample, given the camera ray and its hit if it hit
+    /// anything, or `None` when AOVs are not recorded
+    fn aov_sample(&self, first_hit: Option<(&Ray, &HitRecord)>) -> Option<AovSample> {
+        let materials = self.materials?;
+        Some(match first_hit {
+            Some((r, rec)) => AovSample {
+                depth: (rec.point - r.origin).length(),
+                normal: rec.normal,
+                albedo: rec.material.as_ref().map_or(Color::new(1., 1., 1.), |m| m.albedo()),
+                position: rec.point,
+                material_id: rec.material.as_ref().map_or(0, |m| materials.id(m)),
+                object_id: rec.object_id,
+            },
+            None => AovSample::miss(),
+        })
+    }
+
+    /// Traces the samples of the image pixel (x, y) one after another and adds
+    /// them to the film, stopping early once adaptive sampling considers the
+    /// pixel converged
+    fn render_pixel(&self, sampler: &mut dyn Sampler, film: &mut Film, x: u32, y: u32) {
+        let j = self.image_height - y - 1;
+        let mut samples = Vec::new();
+        let mut stats = PixelStatistics::default();
+        for s in 0..self.samples_per_pixel {
+            let (sx, sy, c, aov) = self.trace_sample(sampler, x, j, self.first_sample + s);
+            stats.add(c);
+            samples.push((sx, sy, c));
+            if let Some(aov) = aov {
+                film.add_aov_sample(x, y, &aov);
+            }
+            if let Some(adaptive) = &self.settings.adaptive {
+                if (s + 1) % adaptive.min_samples() == 0 && adaptive.is_converged(&stats) {
+                    break;
+                }
+            }
+        }
+        splat_pixel(film, x, y, &samples);
+    }
+
+    /// Renders the pixels of a tile into a film covering the tile plus a
+    /// margin for the filter footprint, since samples near the tile edge also
+    /// land on neighbouring pixels
+    fn render_tile(&self, tile: &Tile) -> Film {
+        let filter = self.settings.filter;
+        let margin = filter.radius.ceil() as u32;
+        let x0 = tile.x0.saturating_sub(margin);
+        let y0 = tile.y0.saturating_sub(margin);
+        let x1 = (tile.x0 + tile.width + margin).min(self.image_width);
+        let y1 = (tile.y0 + tile.height + margin).min(self.image_height);
+        let mut film = self.settings.film_region(x0, y0, x1 - x0, y1 - y0);
+        let mut sampler = self.create_sampler();
+        for (x, y) in tile.pixels() {
+            if self.interruptible && self.progress.should_stop() {
+                break;
+            }
+            self.render_pixel(sampler.as_mut(), &mut film, x, y);
+            self.progress.advance(1);
+        }
+        film
+    }
+}
+
+/// Numbers the materials of a scene in the order the objects list them
+struct MaterialIds(HashMap<usize, u32>);
+
+impl MaterialIds {
+    fn new(scene: &HittableList) -> Self {
+        let mut materials = Vec::new();
+        scene.collect_materials(&mut materials);
+        let mut ids = HashMap::new();
+        for m in materials.iter() {
+            let next_id = ids.len() as u32 + 1;
+            ids.entry(material_key(m)).or_insert(next_id);
         }
-        return color(0., 0., 0.);
+        Self(ids)
+    }
+    fn id(&self, material: &Arc<dyn Material + Sync + Send>) -> u32 {
+        self.0.get(&material_key(material)).copied().unwrap_or(0)
+    }
+}
+
+/// Identifies a shared material by the address it points to
+fn material_key(material: &Arc<dyn Material + Sync + Send>) -> usize {
+    Arc::as_ptr(material) as *const () as usize
+}
+
+/// Which of the scene's lights can start light paths. `sample_emission`
+/// only fails for lights that never do, such as directional lights.
+fn emitting_lights(scene: &HittableList) -> Vec<bool> {
+    let mut probe = crate::IndependentSampler::new(0);
+    scene.lights().iter().map(|light| light.sample_emission(&mut probe).is_some()).collect()
+}
+
+/// Splats the samples of the image pixel (x, y) onto the film
+fn splat_pixel(film: &mut Film, x: u32, y: u32, samples: &[(f64, f64, Color)]) {
+    film.add_sample_count(x, y, samples.len() as u32);
+    for &(sx, sy, c) in samples {
+        film.add_sample(sx, sy, c);
     }
-    let unit_direction = r.direction.unit();
-    // Convert y-component (-1 to 1) to blue color
-    let t = 0.5 * (unit_direction.y() + 1.0);
-    return (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0);
 }
 
 pub trait Renderer 
 {
+    fn settings(&self) -> &RenderSettings;
+
+    /// Progress observer, cancellation token and time budget used while rendering
+    fn control(&self) -> &RenderControl {
+        &self.settings().control
+    }
+
+    /// Renders the scene into a linear radiance film, along with the first-hit
+    /// AOVs of the camera samples if the settings record them
+    fn render_film(&self,
+                scene: HittableList, 
+                camera: &dyn CameraModel,                   
+                image_width: u32, 
+                image_height: u32,
+                samples_per_pixel: i32,
+                max_depth: i32) -> Film;
+
     fn render(&self,
                 scene: HittableList, 
-                camera: &Camera,                   
+                camera: &dyn CameraModel,                   
                 image_width: u32, 
                 image_height: u32,
                 samples_per_pixel: i32,
-                max_depth: i32) -> RgbImage;
+                max_depth: i32) -> RgbImage
+    {
+        let mut film = self.render_film(scene, camera, image_width, image_height, samples_per_pixel, max_depth);
+        if let (Some(denoiser), Some(aovs)) = (&self.settings().denoiser, film.aovs()) {
+            film = denoiser.denoise(&film, aovs);
+        }
+        film.to_rgb_image(&self.settings().tone_map)
+    }
 }
 
 #[derive(Default)]
-pub struct SimpleRenderer{}
+pub struct SimpleRenderer{
+    pub settings: RenderSettings,
+}
+impl SimpleRenderer {
+    pub fn new(settings: RenderSettings) -> Self {
+        Self { settings }
+    }
+}
 
 impl Renderer for SimpleRenderer {
-    fn render(&self,
+    fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    fn render_film(&self,
                 scene: HittableList, 
-              camera: &Camera,     
+              camera: &dyn CameraModel,     
               image_width: u32, 
               image_height: u32,
               samples_per_pixel: i32,
-              max_depth: i32) -> RgbImage
+              max_depth: i32) -> Film
     {
-        let mut rng = thread_rng();
-        let mut im = RgbImage::new(image_width, image_height);
-        let pb = indicatif::ProgressBar::new((image_width*image_height).into());
-        pb.set_style(ProgressStyle::default_bar()
-                     .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})"));
-        for j in (0..image_height).rev() {
+        let progress = RenderProgress::start(self.control(), u64::from(image_width * image_height));
+        let materials = self.settings.records_aovs().then(|| MaterialIds::new(&scene));
+        let job = RenderJob {
+            settings: &self.settings,
+            scene: &scene,
+            camera,
+            image_width,
+            image_height,
+            samples_per_pixel: samples_per_pixel.max(0) as u32,
+            first_sample: 0,
+            max_depth,
+            progress: &progress,
+            interruptible: true,
+            materials: materials.as_ref(),
+        };
+        let mut film = self.settings.film_region(0, 0, image_width, image_height);
+        let mut sampler = job.create_sampler();
+        'rows: for j in (0..image_height).rev() {
         // eprint!("\rScanlines remaining: {} ", j);
         for i in 0..image_width {
-            let mut pixel_color = color(0., 0., 0.);
-            for _ in 0..samples_per_pixel {
-                let u = ((i as f64) + rng.gen::<f64>()) / (image_width as f64 - 1.0);
-                let v = ((j as f64) + rng.gen::<f64>()) / (image_height as f64 - 1.0);
-                let r = camera.get_ray(u, v);
-                pixel_color += ray_color(&r, &scene, max_depth);
+            if progress.should_stop() {
+                break 'rows;
             }
-            let pixel = image::Rgb(pixel_color.to_rgb_scaled_gamma2(samples_per_pixel));
-            im.put_pixel(i, image_height - j - 1, pixel);
-            pb.inc(1);
+            job.render_pixel(sampler.as_mut(), &mut film, i, image_height - j - 1);
+            progress.advance(1);
         }
         }
-        pb.finish_with_message("done");
-        im
+        progress.finish();
+        film
     }
 }
 
+use std::collections::HashMap;
 use std::sync::{Arc};
-use std::sync::mpsc;
 use rayon::prelude::*;
-use std::thread;
 
+/// Renders pixels in parallel on the rayon pool
+///
+/// This is a `TileRenderer` with its default tile size and order, so pixels
+/// are gathered into per-tile films rather than sent one by one to a writer.
 #[derive(Default)]
-pub struct RayonRenderer{}
+pub struct RayonRenderer{
+    pub settings: RenderSettings,
+}
+impl RayonRenderer {
+    pub fn new(settings: RenderSettings) -> Self {
+        Self { settings }
+    }
+}
 
 impl Renderer for RayonRenderer {
-    fn render(&self,
+    fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    fn render_film(&self,
               scene: HittableList, 
-              camera: &Camera,     
+              camera: &dyn CameraModel,     
               image_width: u32, 
               image_height: u32,
               samples_per_pixel: i32,
-              max_depth: i32) -> RgbImage
+              max_depth: i32) -> Film
     {
-        let (tx, rx) = mpsc::channel();
-        // Collects and writes pixels to image buffer
-        let writer_thread = thread::spawn(move || {
-            let mut im = RgbImage::new(image_width, image_height);
-            loop {
-                // Type: Option<(u32, u32, Color)>
-                let next_pixel = rx.recv().unwrap();
-                if let Some((x, y, p)) = next_pixel
-                {
-                    im.put_pixel(x, y, p)
-                }else{
-                    break;
-                }
+        TileRenderer::new(self.settings.clone()).render_film(scene, camera, image_width, image_height, samples_per_pixel, max_depth)
+    }
+}
+/// Renders the image in tiles, each on a rayon worker into its own film
+///
+/// Tile films include a margin for the filter footprint and are merged into
+/// the final film in tile order once all tiles are done.
+pub struct TileRenderer{
+    pub settings: RenderSettings,
+    /// Width and height of a tile in pixels
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+}
+impl Default for TileRenderer {
+    fn default() -> Self {
+        Self::new(RenderSettings::default())
+    }
+}
+impl TileRenderer {
+    pub fn new(settings: RenderSettings) -> Self {
+        Self { settings, tile_size: 32, tile_order: TileOrder::default() }
+    }
+    pub fn with_tile_size(mut self, tile_size: u32) -> Self {
+        self.tile_size = tile_size;
+        self
+    }
+    pub fn with_tile_order(mut self, tile_order: TileOrder) -> Self {
+        self.tile_order = tile_order;
+        self
+    }
+}
+
+impl Renderer for TileRenderer {
+    fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    fn render_film(&self,
+              scene: HittableList,
+              camera: &dyn CameraModel,
+              image_width: u32,
+              image_height: u32,
+              samples_per_pixel: i32,
+              max_depth: i32) -> Film
+    {
+        let progress = RenderProgress::start(self.control(), u64::from(image_width * image_height));
+        let materials = self.settings.records_aovs().then(|| MaterialIds::new(&scene));
+        let job = RenderJob {
+            settings: &self.settings,
+            scene: &scene,
+            camera,
+            image_width,
+            image_height,
+            samples_per_pixel: samples_per_pixel.max(0) as u32,
+            first_sample: 0,
+            max_depth,
+            progress: &progress,
+            interruptible: true,
+            materials: materials.as_ref(),
+        };
+        let tiles = make_tiles(image_width, image_height, self.tile_size, self.tile_order);
+        let tile_films: Vec<Film> = tiles
+            .par_iter()
+            .map(|tile| job.render_tile(tile))
+            .collect();
+        progress.finish();
+
+        let mut film = self.settings.film_region(0, 0, image_width, image_height);
+        for tile_film in tile_films.iter() {
+            film.merge(tile_film);
+        }
+        film
+    }
+}
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Renders in passes of a few samples per pixel, accumulated into one film
+///
+/// After a pass the accumulated film is written to the checkpoint file, at
+/// most once per `checkpoint_interval` and always after the last pass. A render
+/// resumed from a checkpoint continues from the next sample index, so it gives
+/// the same image as an uninterrupted render with the same pass size.
+/// Checkpoints do not hold AOVs, so after resuming those cover only the new
+/// passes. Adaptive sampling is not used by this renderer.
+pub struct ProgressiveRenderer{
+    pub settings: RenderSettings,
+    pub samples_per_pass: u32,
+    pub checkpoint_path: Option<PathBuf>,
+    pub checkpoint_interval: Duration,
+    pub resume_from: Option<Checkpoint>,
+}
+impl Default for ProgressiveRenderer {
+    fn default() -> Self {
+        Self::new(RenderSettings::default())
+    }
+}
+impl ProgressiveRenderer {
+    pub fn new(settings: RenderSettings) -> Self {
+        Self {
+            settings: RenderSettings { adaptive: None, ..settings },
+            samples_per_pass: 4,
+            checkpoint_path: None,
+            checkpoint_interval: Duration::from_secs(30),
+            resume_from: None,
+        }
+    }
+    pub fn with_samples_per_pass(mut self, samples_per_pass: u32) -> Self {
+        self.samples_per_pass = samples_per_pass.max(1);
+        self
+    }
+    pub fn with_checkpoint<P: Into<PathBuf>>(mut self, path: P, interval: Duration) -> Self {
+        self.checkpoint_path = Some(path.into());
+        self.checkpoint_interval = interval;
+        self
+    }
+    /// Continues a render of the given size from a checkpoint instead of an
+    /// empty film; fails if the checkpoint was taken with other settings or
+    /// another image size
+    pub fn resume(mut self, checkpoint: Checkpoint, image_width: u32, image_height: u32) -> Result<Self, String> {
+        if !self.can_resume(&checkpoint, image_width, image_height) {
+            return Err("checkpoint was written with different settings or image size".to_string());
+        }
+        self.resume_from = Some(checkpoint);
+        Ok(self)
+    }
+    /// Whether a checkpoint was taken with these settings and image size
+    fn can_resume(&self, checkpoint: &Checkpoint, image_width: u32, image_height: u32) -> bool {
+        checkpoint.seed == self.settings.seed
+            && checkpoint.sampler == self.settings.sampler
+            && *checkpoint.film.filter() == self.settings.filter
+            && checkpoint.film.width() == image_width
+            && checkpoint.film.height() == image_height
+    }
+
+    fn save_checkpoint(&self, film: &Film, samples_taken: u32) {
+        if let Some(path) = &self.checkpoint_path {
+            let checkpoint = Checkpoint {
+                seed: self.settings.seed,
+                sampler: self.settings.sampler,
+                samples_taken,
+                film: film.clone(),
+            };
+            if let Err(e) = checkpoint.save(path) {
+                eprintln!("Failed to write checkpoint {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+impl Renderer for ProgressiveRenderer {
+    fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    fn render_film(&self,
+              scene: HittableList,
+              camera: &dyn CameraModel,
+              image_width: u32,
+              image_height: u32,
+              samples_per_pixel: i32,
+              max_depth: i32) -> Film
+    {
+        let resume_from = self.resume_from.as_ref().filter(|checkpoint| {
+            let matches = self.can_resume(checkpoint, image_width, image_height);
+            if !matches {
+                eprintln!("Checkpoint does not match the render, starting a new one");
             }
-            im
+            matches
         });
+        let (mut film, mut samples_taken) = match resume_from {
+            Some(checkpoint) => {
+                let film = checkpoint.film.clone();
+                (if self.settings.records_aovs() { film.with_aovs() } else { film }, checkpoint.samples_taken)
+            }
+            None => (self.settings.film_region(0, 0, image_width, image_height), 0),
+        };
+        let total_samples = samples_per_pixel.max(0) as u32;
+        let tiles = make_tiles(image_width, image_height, 32, TileOrder::Scanline);
+        // Work units are pixels times the passes still to render
+        let remaining = total_samples.saturating_sub(samples_taken);
+        let passes = remaining.div_ceil(self.samples_per_pass);
+        let progress = RenderProgress::start(self.control(), u64::from(image_width * image_height) * u64::from(passes));
+        let mut last_checkpoint = Instant::now();
+        let materials = self.settings.records_aovs().then(|| MaterialIds::new(&scene));
+        while samples_taken < total_samples {
+            // Passes always run to completion so that checkpoints hold whole passes
+            if progress.should_stop() {
+                self.save_checkpoint(&film, samples_taken);
+                break;
+            }
+            let pass_samples = self.samples_per_pass.min(total_samples - samples_taken);
+            let job = RenderJob {
+                settings: &self.settings,
+                scene: &scene,
+                camera,
+                image_width,
+                image_height,
+                samples_per_pixel: pass_samples,
+                first_sample: samples_taken,
+                max_depth,
+                progress: &progress,
+                interruptible: false,
+                materials: materials.as_ref(),
+            };
+            let tile_films: Vec<Film> = tiles
+                .par_iter()
+                .map(|tile| job.render_tile(tile))
+                .collect();
+            for tile_film in tile_films.iter() {
+                film.merge(tile_film);
+            }
+            samples_taken += pass_samples;
+
+            if samples_taken == total_samples || last_checkpoint.elapsed() >= self.checkpoint_interval {
+                self.save_checkpoint(&film, samples_taken);
+                last_checkpoint = Instant::now();
+            }
+        }
+        progress.finish();
+        film
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_bounce_limits_per_kind() {
+        let limits = BounceLimits { diffuse: 2, specular: 0, ..BounceLimits::default() };
+        let mut counts = BounceCounts::default();
+        assert!(counts.add(BounceKind::Diffuse, &limits));
+        assert!(counts.add(BounceKind::Transmission, &limits));
+        assert!(counts.add(BounceKind::Diffuse, &limits));
+        assert!(!counts.add(BounceKind::Diffuse, &limits));
+        assert!(!BounceCounts::default().add(BounceKind::Specular, &limits));
+    }
+    #[test]
+    fn test_roulette_survival() {
+        let roulette = RussianRoulette::default();
+        assert_eq!(roulette.survival_probability(color(2., 0., 0.)), 0.95);
+        assert_eq!(roulette.survival_probability(color(0.1, 0.3, 0.2)), 0.3);
+    }
+    #[test]
+    fn test_integrator_names() {
+        for name in ["path", "ao", "normals", "facing", "uv", "bounces", "whitted"] {
+            assert_eq!(name.parse::<Integrator>().unwrap().to_string(), name);
+        }
+        assert!("whitted-ish".parse::<Integrator>().is_err());
+        assert_eq!(heatmap(0.), color(0., 0., 1.));
+        assert_eq!(heatmap(1.), color(1., 0., 0.));
+    }
+    #[test]
+    fn test_debug_integrators_shade_first_hit() {
+        let mut world = HittableList::new();
+        world.add(Box::new(crate::SimpleSphere::new(crate::Point3::new(0., 0., -2.), 1.)));
+        let settings = RenderSettings::default();
+        let mut sampler = crate::IndependentSampler::new(0);
+        let head_on = Ray::new(crate::Point3::default(), crate::Vec3::new(0., 0., -1.));
+        let mut trace = |integrator: Integrator, r: &Ray| integrator.trace(r, world.hit(r, 0.001, f64::INFINITY), &world, 10, &settings, &mut sampler);
+        assert_eq!(trace(Integrator::Normals, &head_on), color(0.5, 0.5, 1.));
+        assert_eq!(trace(Integrator::FacingRatio, &head_on), color(1., 1., 1.));
+        // Nothing but the sphere itself to block the hemisphere
+        assert_eq!(trace(Integrator::AmbientOcclusion { radius: 10. }, &head_on), color(1., 1., 1.));
+        let miss = Ray::new(crate::Point3::default(), crate::Vec3::new(0., 1., 0.));
+        assert_eq!(trace(Integrator::Normals, &miss), Color::default());
+    }
+    #[test]
+    fn test_aovs_recorded_with_beauty_pass() {
+        let scene = || {
+            let mut world = HittableList::new();
+            world.add(Box::new(crate::SimpleSphere::new(crate::Point3::new(0., 0., -2.), 1.)));
+            world
+        };
+        let camera = crate::Camera::new(crate::Point3::default(), crate::Point3::new(0., 0., -1.), crate::Vec3::new(0., 1., 0.), 90., 1.);
+        let settings = RenderSettings { aovs: true, ..RenderSettings::default() };
+        let simple = SimpleRenderer::new(settings.clone()).render_film(scene(), &camera, 16, 16, 2, 5);
+        let tiled = TileRenderer::new(settings).render_film(scene(), &camera, 16, 16, 2, 5);
+        let (simple, tiled) = (simple.aovs().unwrap(), tiled.aovs().unwrap());
+        assert!((simple.depth(8, 8) - 1.).abs() < 0.1);
+        assert_eq!(simple.depth(0, 0), f64::INFINITY);
+        for (x, y) in [(0, 0), (5, 9), (8, 8), (15, 15)] {
+            assert_eq!(simple.depth(x, y), tiled.depth(x, y));
+            assert_eq!(simple.normal(x, y), tiled.normal(x, y));
+        }
+        assert!(SimpleRenderer::new(RenderSettings::default()).render_film(scene(), &camera, 4, 4, 1, 5).aovs().is_none());
+    }
+    #[test]
+    fn test_negative_samples_render_nothing() {
+        let camera = crate::Camera::default();
+        let film = TileRenderer::new(RenderSettings::default()).render_film(HittableList::new(), &camera, 4, 4, -1, 5);
+        assert_eq!(film.sample_count(2, 2), 0);
+    }
+    #[test]
+    fn test_resume_checks_checkpoint() {
+        let settings = RenderSettings::default();
+        let checkpoint = || Checkpoint {
+            seed: settings.seed,
+            sampler: settings.sampler,
+            samples_taken: 4,
+            film: Film::new(4, 4, settings.filter),
+        };
+        let renderer = || ProgressiveRenderer::new(settings.clone());
+        assert!(renderer().resume(checkpoint(), 4, 4).is_ok());
+        assert!(renderer().resume(checkpoint(), 8, 4).is_err());
+    }
+    #[test]
+    fn test_whitted_mirror_and_glass() {
+        let mut world = HittableList::new();
+        let gold = color(0.8, 0.6, 0.2);
+        world.add(Box::new(crate::Sphere::new(crate::Point3::new(0., 0., -2.), 1., Arc::new(crate::Metal::new(gold, 0.5)))));
+        let head_on = Ray::new(crate::Point3::default(), crate::Vec3::new(0., 0., -1.));
+        // Straight back towards the camera, whatever the fuzz
+        let back = Ray::new(crate::Point3::new(0., 0., -1.), crate::Vec3::new(0., 0., 1.));
+        assert_eq!(whitted_color(&head_on, world.hit(&head_on, 0.001, f64::INFINITY), &world, 5, color(1., 1., 1.), &Vec::new()), gold * world.background(&back, true));
 
-        let scene = Arc::new(scene);
-        // let mut im = RgbImage::new(image_width, image_height);
-        let n_pixels = image_width * image_height;
-        let pb = indicatif::ProgressBar::new(n_pixels.into());
-        pb.set_style(ProgressStyle::default_bar()
-                     .template("{spinner:.green} [{bar:40.cyan/blue}] {percent}% ({elapsed_precise}/{eta_precise})"));
-        let tx2 = tx.clone();
-        // Column major form
-        // pixel_idx = i * image_height + j
-        (0..n_pixels)
-            .into_par_iter()
-            .progress_with(pb)
-            .map_with(scene, |scene, pixel_idx| {
-                let j = image_height - (pixel_idx % image_height) - 1;
-                let i = (pixel_idx as f64 / image_height as f64).floor() as u32;
-                let pixel_color: Color = (0..samples_per_pixel)
-                    .into_par_iter()
-                    .map(|_| {
-                        let mut rng = thread_rng();
-                        let u = ((i as f64) + rng.gen::<f64>()) / (image_width as f64 - 1.0);
-                        let v = ((j as f64) + rng.gen::<f64>()) / (image_height as f64 - 1.0);
-                        let r = camera.get_ray(u, v);
-                        ray_color(&r, &scene, max_depth)
-                    }).reduce(Color::default, |p, c| p + c);
-                let pixel = image::Rgb(pixel_color.to_rgb_scaled_gamma2(samples_per_pixel));
-                // im.put_pixel(i, image_height - j - 1, pixel);
-                return Some((i, image_height - j - 1, pixel));
-            }).try_for_each_with(tx, |tx, item| {
-                tx.send(item)
-            }).unwrap();
-        tx2.send(None).unwrap();
-
-        let im = writer_thread.join().unwrap();
-        im
-    }
-}
\ No newline at end of file
+        let glass = crate::Dielectric::new(1.5);
+        let rec = world.hit(&head_on, 0.001, f64::INFINITY).unwrap();
+        let rays = glass.specular_rays(&head_on, &rec);
+        assert_eq!(rays.len(), 2);
+        assert!((rays[0].1.x() + rays[1].1.x() - 1.).abs() < 1e-12);
+    }
+    #[test]
+    fn test_nested_media() {
+        let water: Arc<dyn Material + Sync + Send> = Arc::new(crate::Dielectric::new(1.33).with_medium(Medium::tinted(color(0.5, 0.8, 0.9), 1.)));
+        let bubble: Arc<dyn Material + Sync + Send> = Arc::new(crate::Dielectric::new(1. / 1.33));
+        let r = Ray::new(crate::Point3::default(), crate::Vec3::new(0., 0., -1.));
+        let boundary = |material: &Arc<dyn Material + Sync + Send>, entering: bool| {
+            let normal = crate::Vec3::new(0., 0., if entering { 1. } else { -1. });
+            HitRecord::new_with_material(normal, &r, 1., crate::Point3::default(), material.clone())
+        };
+        let mut inside = Vec::new();
+        cross_boundary(&mut inside, &boundary(&water, true));
+        assert!(current_medium(&inside).is_some());
+        cross_boundary(&mut inside, &boundary(&bubble, true));
+        assert!(current_medium(&inside).is_none());
+        cross_boundary(&mut inside, &boundary(&bubble, false));
+        assert_eq!(current_medium(&inside), water.interior());
+        cross_boundary(&mut inside, &boundary(&water, false));
+        assert!(inside.is_empty());
+    }
+    #[test]
+    fn test_subsurface_furnace() {
+        // Under uniform white light a thick translucent sphere looks about as
+        // bright as a diffuse one of the same albedo
+        struct White;
+        impl crate::Environment for White {
+            fn radiance(&self, _direction: crate::Vec3) -> Color {
+                color(1., 1., 1.)
+            }
+        }
+        for albedo in [0.4, 0.8] {
+            let mut world = HittableList::new();
+            world.set_environment(Arc::new(White));
+            let material = crate::Subsurface::new(color(albedo, albedo, albedo), color(0.05, 0.05, 0.05));
+            world.add(Box::new(crate::Sphere::new(crate::Point3::new(0., 0., -3.), 1., Arc::new(material))));
+            let mut sampler = crate::IndependentSampler::new(1);
+            let n = 1000;
+            let mut sum = 0.;
+            for i in 0..n {
+                sampler.start_pixel_sample(i, 0, 0);
+                let x = f64::from(i) / f64::from(n) * 1.6 - 0.8;
+                let r = Ray::new(crate::Point3::new(x, 0., 0.), crate::Vec3::new(0., 0., -1.));
+                sum += ray_color(&r, world.hit(&r, 0.001, f64::INFINITY), &world, 50, &RenderSettings::default(), &mut sampler).0.x();
+            }
+            let mean = sum / f64::from(n);
+            assert!((mean - albedo).abs() < 0.1, "{} for albedo {}", mean, albedo);
+        }
+    }
+}
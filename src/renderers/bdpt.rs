@@ -0,0 +1,533 @@
+use super::{emitting_lights, splat_pixel, MaterialIds, PixelSamples, RenderJob, RenderSettings, Renderer};
+use crate::{AovSample, CameraModel, Color, Film, HitRecord, Hittable, HittableList, Light, Point3, Ray, RenderProgress, Sampler};
+use crate::{BOUNCE_DIMENSION, DIMENSIONS_PER_BOUNCE, LIGHT_PATH_DIMENSION, ROULETTE_DIMENSION};
+use rayon::prelude::*;
+
+/// Rows rendered in parallel before their light splats are added to the film
+const ROWS_PER_BATCH: u32 = 16;
+
+/// Bidirectional path tracer (Veach 1997)
+///
+/// Each camera sample traces a path from the camera and one from every light
+/// that can start paths (point and spot lights), then connects every vertex of
+/// one to every vertex of the other. The contributions are weighted with the
+/// balance heuristic over all the ways the same path could have been
+/// sampled, so light reaching the camera through glass and mirrors (caustics)
+/// is found by paths from the lights, and direct light by shadow rays.
+/// Connections of light path vertices to the camera are splatted onto the
+/// pixels around where they land, spread by the reconstruction filter.
+///
+/// Only the thin lens `Camera` can be reached by light paths; with other
+/// cameras those connections are skipped. Materials without a density for
+/// their scattered directions, such as fuzzy metal, are treated as specular.
+/// Adaptive sampling, bounce limits and interior media are not supported;
+/// Russian roulette applies to both kinds of path.
+#[derive(Default)]
+pub struct BdptRenderer {
+    pub settings: RenderSettings,
+}
+
+impl BdptRenderer {
+    pub fn new(settings: RenderSettings) -> Self {
+        Self { settings }
+    }
+}
+
+impl Renderer for BdptRenderer {
+    fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    fn render_film(&self,
+              scene: HittableList,
+              camera: &dyn CameraModel,
+              image_width: u32,
+              image_height: u32,
+              samples_per_pixel: i32,
+              max_depth: i32) -> Film
+    {
+        let progress = RenderProgress::start(self.control(), u64::from(image_width * image_height));
+        let materials = self.settings.records_aovs().then(|| MaterialIds::new(&scene));
+        let job = RenderJob {
+            settings: &self.settings,
+            scene: &scene,
+            camera,
+            image_width,
+            image_height,
+            samples_per_pixel: samples_per_pixel.max(0) as u32,
+            first_sample: 0,
+            max_depth,
+            progress: &progress,
+            interruptible: true,
+            materials: materials.as_ref(),
+        };
+        let emitters = emitting_lights(&scene);
+        let integrator = Bdpt { job: &job, emitters: &emitters };
+
+        let mut film = self.settings.film_region(0, 0, image_width, image_height);
+        let mut light_image = Film::new(image_width, image_height, self.settings.filter);
+        let mut camera_samples = 0u64;
+        let mut y0 = 0;
+        while y0 < image_height && !progress.should_stop() {
+            let rows: Vec<RowResult> = (y0..(y0 + ROWS_PER_BATCH).min(image_height))
+                .into_par_iter()
+                .map(|y| {
+                    let mut row = RowResult::default();
+                    if progress.should_stop() {
+                        return row;
+                    }
+                    let mut sampler = job.create_sampler();
+                    let j = image_height - y - 1;
+                    for i in 0..image_width {
+                        let mut samples = Vec::new();
+                        let mut aovs = Vec::new();
+                        for index in 0..job.samples_per_pixel {
+                            let (sx, sy, c, aov) = integrator.trace(sampler.as_mut(), i, j, index, &mut row.splats);
+                            samples.push((sx, sy, c));
+                            aovs.extend(aov);
+                        }
+                        row.pixels.push((i, y, samples, aovs));
+                    }
+                    progress.advance(u64::from(image_width));
+                    row
+                })
+                .collect();
+            // Added in row order so that the image does not depend on the thread scheduling
+            for row in rows.iter() {
+                for (x, y, samples, aovs) in row.pixels.iter() {
+                    splat_pixel(&mut film, *x, *y, samples);
+                    for aov in aovs.iter() {
+                        film.add_aov_sample(*x, *y, aov);
+                    }
+                    camera_samples += samples.len() as u64;
+                }
+                for &(x, y, c) in row.splats.iter() {
+                    light_image.add_splat(x, y, c);
+                }
+            }
+            y0 += ROWS_PER_BATCH;
+        }
+        progress.finish();
+
+        // Each camera sample traced one path per light, and the camera
+        // importance is normalized over the image plane, which spans
+        // (width - 1) x (height - 1) pixels between the outermost sample positions
+        if camera_samples > 0 {
+            let scale = f64::from((image_width - 1).max(1) * (image_height - 1).max(1)) / camera_samples as f64;
+            film.add_splats(&light_image, scale);
+        }
+        film
+    }
+}
+
+#[derive(Default)]
+struct RowResult {
+    /// Camera samples and first-hit AOVs of each pixel
+    pixels: Vec<(u32, u32, PixelSamples, Vec<AovSample>)>,
+    /// Film positions and radiance of light path connections to the camera
+    splats: Vec<(f64, f64, Color)>,
+}
+
+/// Vertex of a camera or light path; only surface vertices have a hit record
+struct Vertex {
+    point: Point3,
+    rec: Option<HitRecord>,
+    /// Point the path came from, for surfaces
+    previous: Point3,
+    /// Product of the scattering weights up to and including this vertex
+    beta: Color,
+    /// Area density of sampling this vertex from its own end of the path
+    pdf_fwd: f64,
+    /// Area density of sampling it from the other end
+    pdf_rev: f64,
+    /// Scatters into discrete directions, so it cannot be connected to
+    delta: bool,
+}
+
+impl Vertex {
+    fn new(point: Point3, beta: Color, pdf_fwd: f64) -> Self {
+        Self { point, rec: None, previous: point, beta, pdf_fwd, pdf_rev: 0., delta: false }
+    }
+
+    /// Scattered fraction of light arriving from `to`, towards the previous
+    /// vertex, including the cosine at this vertex
+    fn f(&self, to: Point3) -> Color {
+        match &self.rec {
+            Some(rec) => rec.material.as_ref().map_or(Color::default(), |m| {
+                m.eval(&Ray::new(self.previous, self.point - self.previous), rec, (to - self.point).unit())
+            }),
+            None => Color::default(),
+        }
+    }
+
+    /// Solid angle density of scattering towards `to` after arriving from `from`
+    fn pdf(&self, from: Point3, to: Point3) -> f64 {
+        match &self.rec {
+            Some(rec) if !self.delta => rec.material.as_ref().map_or(0., |m| {
+                m.pdf(&Ray::new(from, self.point - from), rec, (to - self.point).unit())
+            }),
+            _ => 0.,
+        }
+    }
+
+    /// Area density at `next` of a solid angle density of directions leaving this vertex
+    fn to_area(&self, pdf: f64, next: &Vertex) -> f64 {
+        let d = next.point - self.point;
+        let distance_squared = d.length_squared();
+        let cos = match &next.rec {
+            Some(rec) => rec.normal.dot(d).abs() / distance_squared.sqrt(),
+            None => 1.,
+        };
+        pdf * cos / distance_squared
+    }
+}
+
+struct Bdpt<'a> {
+    job: &'a RenderJob<'a>,
+    /// Which of the scene's lights start light paths, each traced once per camera sample
+    emitters: &'a [bool],
+}
+
+/// How the two subpaths of a strategy are joined
+enum Connection<'v> {
+    /// Edge between the last vertices of both paths
+    Paths,
+    /// Light path vertex joined to a sampled lens point, with the camera's
+    /// solid angle density of the direction towards the vertex
+    Camera(&'v Vertex, f64),
+    /// Camera path vertex joined to a light by a shadow ray, with the
+    /// light's solid angle density of emitting towards the vertex
+    Light(&'v Vertex, f64),
+}
+
+impl<'a> Bdpt<'a> {
+    /// Dimensions used by each vertex of a light path: scattering and
+    /// roulette, then the lens position of its connection to the camera
+    const LIGHT_VERTEX_DIMENSIONS: u32 = 2 * DIMENSIONS_PER_BOUNCE;
+
+    fn max_depth(&self) -> usize {
+        self.job.max_depth.max(0) as usize
+    }
+
+    fn light_dimension(&self, light: usize, vertex: usize) -> u32 {
+        let per_light = Self::LIGHT_VERTEX_DIMENSIONS * (self.max_depth() as u32 + 2);
+        LIGHT_PATH_DIMENSION + per_light * light as u32 + Self::LIGHT_VERTEX_DIMENSIONS * vertex as u32
+    }
+
+    /// Radiance of sample `index` of pixel (i, j) through all connection
+    /// strategies, with the AOVs of its first hit like `RenderJob::trace_sample`;
+    /// light paths reaching the camera elsewhere go to `splats`
+    fn trace(&self, sampler: &mut dyn Sampler, i: u32, j: u32, index: u32, splats: &mut Vec<(f64, f64, Color)>) -> (f64, f64, Color, Option<AovSample>) {
+        let job = self.job;
+        let (x, y, ray) = job.camera_ray(sampler, i, j, index);
+        let ray = match ray {
+            Some(ray) => ray,
+            None => return (x, y, Color::default(), job.aov_sample(None)),
+        };
+        let first_ray = Ray::new(ray.origin, ray.direction);
+        let white = Color::new(1., 1., 1.);
+        let pdf_dir = job.camera.direction_pdf(ray.direction);
+        let mut camera_vertex = Vertex::new(ray.origin, white, 1.);
+        // A camera that light paths cannot reach acts like a specular vertex
+        camera_vertex.delta = pdf_dir <= 0.;
+        let reaches_camera = !camera_vertex.delta;
+        let mut camera_path = vec![camera_vertex];
+        let mut radiance = self.random_walk(&mut camera_path, ray, pdf_dir, white, sampler, None);
+        let aov = job.aov_sample(camera_path.get(1).and_then(|v| v.rec.as_ref()).map(|rec| (&first_ray, rec)));
+
+        let lights = job.scene.lights();
+        let light_paths: Vec<Vec<Vertex>> = lights
+            .iter()
+            .enumerate()
+            .map(|(k, light)| {
+                if !self.emitters[k] {
+                    return Vec::new();
+                }
+                sampler.set_dimension(self.light_dimension(k, 0));
+                let emission = match light.sample_emission(sampler) {
+                    Some(e) if e.pdf > 0. => e,
+                    _ => return Vec::new(),
+                };
+                let mut path = vec![Vertex::new(emission.origin, emission.intensity, 1.)];
+                let ray = Ray::new(emission.origin, emission.direction);
+                self.random_walk(&mut path, ray, emission.pdf, emission.intensity / emission.pdf, sampler, Some(k));
+                path
+            })
+            .collect();
+
+        for t in 2..=camera_path.len() {
+            let z = &camera_path[t - 1];
+            if z.delta {
+                continue;
+            }
+            // s = 1: shadow rays to every light
+            for (k, light) in lights.iter().enumerate() {
+                if let Some(c) = self.connect_light(&camera_path[..t], light.as_ref(), self.emitters[k]) {
+                    radiance += c;
+                }
+            }
+            // s >= 2: edges to the vertices of each light path
+            for path in light_paths.iter() {
+                for s in 2..=path.len().min(self.max_depth() + 2 - t) {
+                    let y = &path[s - 1];
+                    if y.delta {
+                        continue;
+                    }
+                    let c = z.beta * z.f(y.point) * y.f(z.point) * y.beta / (y.point - z.point).length_squared();
+                    if c == Color::default() || self.occluded(z.point, y.point) {
+                        continue;
+                    }
+                    radiance += mis_weight(&camera_path[..t], &path[..s], Connection::Paths) * c;
+                }
+            }
+        }
+
+        // t = 1: edges from light path vertices to the lens
+        if reaches_camera {
+            for (k, path) in light_paths.iter().enumerate() {
+                for s in 2..=path.len().min(self.max_depth() + 1) {
+                    let y = &path[s - 1];
+                    if y.delta {
+                        continue;
+                    }
+                    sampler.set_dimension(self.light_dimension(k, s - 1) + DIMENSIONS_PER_BOUNCE);
+                    let sample = match job.camera.sample_importance(y.point, sampler) {
+                        Some(sample) => sample,
+                        None => continue,
+                    };
+                    // The camera rays of the last column and row reach past s = 1 and t = 1
+                    let film_x = sample.s * (job.image_width as f64 - 1.);
+                    let film_y = job.image_height as f64 - sample.t * (job.image_height as f64 - 1.);
+                    let on_film = (0. ..job.image_width as f64).contains(&film_x) && film_y > 0. && film_y <= job.image_height as f64;
+                    if !on_film {
+                        continue;
+                    }
+                    let c = y.beta * y.f(sample.lens_point) * sample.weight;
+                    if c == Color::default() || self.occluded(y.point, sample.lens_point) {
+                        continue;
+                    }
+                    let lens = Vertex::new(sample.lens_point, white, 1.);
+                    let pdf_dir = job.camera.direction_pdf(y.point - sample.lens_point);
+                    let weight = mis_weight(&[], &path[..s], Connection::Camera(&lens, pdf_dir));
+                    splats.push((film_x, film_y, weight * c));
+                }
+            }
+        }
+        (x, y, radiance, aov)
+    }
+
+    /// Extends `path` by following `ray`, whose direction was sampled with
+    /// solid angle density `pdf_dir` and which carries `beta`. Camera paths
+    /// (`light` is `None`) return the light they reach from the environment
+    /// and from lights with a visible extent.
+    fn random_walk(&self, path: &mut Vec<Vertex>, mut ray: Ray, mut pdf_dir: f64, beta: Color, sampler: &mut dyn Sampler, light: Option<usize>) -> Color {
+        let scene = self.job.scene;
+        let mut radiance = Color::default();
+        let mut throughput = Color::new(1., 1., 1.);
+        let mut sees_lights = true;
+        for bounce in 0..self.max_depth() {
+            let rec = match scene.hit(&ray, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None => {
+                    if light.is_none() {
+                        radiance += throughput * scene.background(&ray, sees_lights);
+                    }
+                    break;
+                }
+            };
+            let direction_in = ray.direction.unit();
+            let distance_squared = (rec.point - ray.origin).length_squared();
+            let mut vertex = Vertex::new(rec.point, beta * throughput, 0.);
+            vertex.previous = ray.origin;
+            vertex.pdf_fwd = pdf_dir * rec.normal.dot(direction_in).abs() / distance_squared;
+            vertex.delta = !rec.shades_lights_directly();
+            sees_lights = vertex.delta;
+
+            let base = match light {
+                Some(k) => self.light_dimension(k, bounce + 1),
+                None => BOUNCE_DIMENSION + DIMENSIONS_PER_BOUNCE * bounce as u32,
+            };
+            sampler.set_dimension(base);
+            let scattered = rec.scatter(&ray, sampler);
+            let (pdf_next, pdf_back) = match (&scattered, &rec.material) {
+                (Some((scattered, _)), Some(m)) if !vertex.delta => {
+                    let direction_out = scattered.direction.unit();
+                    let back = Ray::new(rec.point + direction_out, -direction_out);
+                    (m.pdf(&ray, &rec, direction_out), m.pdf(&back, &rec, -direction_in))
+                }
+                _ => (0., 0.),
+            };
+            // Density of sampling the previous vertex from this one
+            let prev = path.last_mut().unwrap();
+            let prev_cos = prev.rec.as_ref().map_or(1., |r| r.normal.dot(direction_in).abs());
+            prev.pdf_rev = pdf_back * prev_cos / distance_squared;
+            vertex.rec = Some(rec);
+            path.push(vertex);
+
+            let (scattered, attenuation) = match scattered {
+                Some(scatter) => scatter,
+                None => break,
+            };
+            throughput = throughput * attenuation;
+            if let Some(roulette) = &self.job.settings.russian_roulette {
+                if bounce as u32 + 1 >= roulette.min_bounces {
+                    let survival = roulette.survival_probability(throughput);
+                    sampler.set_dimension(base + ROULETTE_DIMENSION);
+                    if sampler.get_1d() >= survival {
+                        break;
+                    }
+                    throughput /= survival;
+                }
+            }
+            pdf_dir = pdf_next;
+            ray = scattered;
+        }
+        radiance
+    }
+
+    fn occluded(&self, a: Point3, b: Point3) -> bool {
+        let d = b - a;
+        let distance = d.length();
+        self.job.scene.occluded(&Ray::new(a, d / distance), 0.001, distance - 0.001)
+    }
+
+    /// Light from `light` at the last vertex of the camera path, through a shadow ray
+    fn connect_light(&self, camera_path: &[Vertex], light: &(dyn Light + Sync + Send), starts_paths: bool) -> Option<Color> {
+        let z = camera_path.last().unwrap();
+        let sample = light.sample(z.point)?;
+        let f = z.f(z.point + sample.direction);
+        if f == Color::default() {
+            return None;
+        }
+        if self.job.scene.occluded(&Ray::new(z.point, sample.direction), 0.001, sample.distance) {
+            return None;
+        }
+        let c = z.beta * f * sample.irradiance;
+        if !starts_paths {
+            // No other strategy reaches lights without light paths
+            return Some(c);
+        }
+        let vertex = Vertex::new(z.point + sample.direction * sample.distance, Color::default(), 1.);
+        let pdf_dir = light.emission_pdf(-sample.direction);
+        Some(mis_weight(camera_path, &[], Connection::Light(&vertex, pdf_dir)) * c)
+    }
+}
+
+/// Balance heuristic weight of the strategy joining `camera_path` and
+/// `light_path`
+///
+/// The densities of the vertices next to the connection are recomputed for
+/// sampling them from the other end, then the density of every other strategy
+/// producing the same path is compared with this one's, as a running product
+/// of the ratios at each vertex (Veach 1997, section 10.2).
+fn mis_weight(camera_path: &[Vertex], light_path: &[Vertex], connection: Connection) -> f64 {
+    /// Area densities of a vertex from each end of the path
+    struct Densities {
+        from_light: f64,
+        from_camera: f64,
+        delta: bool,
+    }
+    let mut light_side: Vec<&Vertex> = light_path.iter().collect();
+    let mut camera_side: Vec<&Vertex> = camera_path.iter().collect();
+    match connection {
+        Connection::Camera(lens, _) => camera_side.push(lens),
+        Connection::Light(light, _) => light_side.push(light),
+        Connection::Paths => {}
+    }
+    let (s, t) = (light_side.len(), camera_side.len());
+    let mut ys: Vec<Densities> = light_side
+        .iter()
+        .map(|v| Densities { from_light: v.pdf_fwd, from_camera: v.pdf_rev, delta: v.delta })
+        .collect();
+    let mut zs: Vec<Densities> = camera_side
+        .iter()
+        .map(|v| Densities { from_light: v.pdf_rev, from_camera: v.pdf_fwd, delta: v.delta })
+        .collect();
+    let (y, z) = (light_side[s - 1], camera_side[t - 1]);
+    ys[s - 1].delta = false;
+    zs[t - 1].delta = false;
+
+    zs[t - 1].from_light = match connection {
+        Connection::Light(light, pdf_dir) => light.to_area(pdf_dir, z),
+        _ => y.to_area(y.pdf(light_side[s - 2].point, z.point), z),
+    };
+    ys[s - 1].from_camera = match connection {
+        Connection::Camera(lens, pdf_dir) => lens.to_area(pdf_dir, y),
+        _ => z.to_area(z.pdf(camera_side[t - 2].point, y.point), y),
+    };
+    if t >= 2 {
+        let before = camera_side[t - 2];
+        zs[t - 2].from_light = z.to_area(z.pdf(y.point, before.point), before);
+    }
+    if s >= 2 {
+        let before = light_side[s - 2];
+        ys[s - 2].from_camera = y.to_area(y.pdf(z.point, before.point), before);
+    }
+
+    // Specular vertices have zero densities, which cancel along the path
+    let remap = |p: f64| if p != 0. { p } else { 1. };
+    let mut sum = 0.;
+    // Strategies with fewer camera vertices; zs[0] is on the camera
+    let mut ratio = 1.;
+    for i in (1..t).rev() {
+        ratio *= remap(zs[i].from_light) / remap(zs[i].from_camera);
+        if !zs[i].delta && !zs[i - 1].delta {
+            sum += ratio;
+        }
+    }
+    // Strategies with fewer light vertices; the lights are points, which
+    // camera paths cannot reach, so ys[0] is always on the light
+    ratio = 1.;
+    for i in (1..s).rev() {
+        ratio *= remap(ys[i].from_camera) / remap(ys[i].from_light);
+        if !ys[i].delta && !ys[i - 1].delta {
+            sum += ratio;
+        }
+    }
+    1. / (1. + sum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderers::RayonRenderer;
+    use crate::{Camera, Environment, Lambertian, PointLight, Sphere, Vec3};
+    use std::sync::Arc;
+
+    struct Black;
+    impl Environment for Black {
+        fn radiance(&self, _direction: Vec3) -> Color {
+            Color::default()
+        }
+    }
+
+    fn mean(film: &Film) -> f64 {
+        let mut sum = 0.;
+        for y in 0..film.height() {
+            for x in 0..film.width() {
+                let c = film.pixel(x, y);
+                sum += c.x() + c.y() + c.z();
+            }
+        }
+        sum / f64::from(3 * film.width() * film.height())
+    }
+
+    #[test]
+    fn test_matches_path_tracer_on_diffuse_scene() {
+        let scene = || {
+            let mut world = HittableList::new();
+            let grey = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+            world.add(Box::new(Sphere::new(Point3::new(0., -100.5, -1.), 100., grey.clone())));
+            world.add(Box::new(Sphere::new(Point3::new(0., 0., -1.), 0.5, grey)));
+            world.add_light(Arc::new(PointLight::new(Point3::new(1., 2., 0.), Color::new(4., 4., 4.))));
+            world.set_environment(Arc::new(Black));
+            world
+        };
+        let camera = Camera::new(Point3::new(0., 0.5, 1.), Point3::new(0., 0., -1.), Vec3::new(0., 1., 0.), 60., 4. / 3.);
+        let settings = RenderSettings::default();
+        let path_traced = mean(&RayonRenderer::new(settings.clone()).render_film(scene(), &camera, 16, 12, 64, 8));
+        let bidirectional = mean(&BdptRenderer::new(settings).render_film(scene(), &camera, 16, 12, 64, 8));
+        assert!(path_traced > 0.);
+        assert!((bidirectional - path_traced).abs() < 0.05 * path_traced, "{} vs {}", bidirectional, path_traced);
+    }
+}
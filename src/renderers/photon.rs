@@ -0,0 +1,428 @@
+use super::{emitting_lights, MaterialIds, RenderJob, RenderSettings, Renderer};
+use crate::{AovSample, CameraModel, Color, Film, HitRecord, Hittable, HittableList, Point3, Ray, RenderProgress, Sampler, Vec3};
+use crate::{DIMENSIONS_PER_BOUNCE, LIGHT_PATH_DIMENSION, ROULETTE_DIMENSION};
+use rayon::prelude::*;
+use std::f64::consts::PI;
+
+/// Stochastic progressive photon mapping (Hachisuka and Jensen 2009)
+///
+/// Every pass traces one camera path per pixel through mirrors and glass to
+/// its first diffuse surface, then photons from the lights that can start
+/// paths (point and spot lights). Photons are stored where they land on
+/// diffuse surfaces after at least one bounce, in a kd-tree searched around
+/// each pixel's diffuse point. The search radius of a pixel shrinks with the
+/// photons it has found, so the blur of the estimate vanishes as passes are
+/// added, and caustics seen through or cast by glass converge where path
+/// tracing with point lights cannot find them at all.
+///
+/// Direct light at the diffuse points comes from shadow rays to the lights
+/// and one scattered ray towards the environment; light from the environment
+/// and from directional lights is not carried further, as they emit no
+/// photons. Each sample per pixel is one pass. Adaptive sampling, bounce
+/// limits and interior media are not supported; Russian roulette applies to
+/// the photons.
+pub struct PhotonMapRenderer {
+    pub settings: RenderSettings,
+    /// Photons traced from the lights in each pass
+    pub photons_per_pass: u32,
+    /// Search radius of every pixel in the first pass, in scene units
+    pub initial_radius: f64,
+    /// Fraction of the photons found in a pass kept when shrinking the radius, in (0, 1)
+    pub alpha: f64,
+}
+impl Default for PhotonMapRenderer {
+    fn default() -> Self {
+        Self::new(RenderSettings::default())
+    }
+}
+impl PhotonMapRenderer {
+    pub fn new(settings: RenderSettings) -> Self {
+        Self { settings, photons_per_pass: 100_000, initial_radius: 0.1, alpha: 0.7 }
+    }
+    pub fn with_photons_per_pass(mut self, photons_per_pass: u32) -> Self {
+        self.photons_per_pass = photons_per_pass.max(1);
+        self
+    }
+    pub fn with_initial_radius(mut self, initial_radius: f64) -> Self {
+        self.initial_radius = initial_radius;
+        self
+    }
+    pub fn with_alpha(mut self, alpha: f64) -> Self {
+        self.alpha = alpha;
+        self
+    }
+}
+
+impl Renderer for PhotonMapRenderer {
+    fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    fn render_film(&self,
+              scene: HittableList,
+              camera: &dyn CameraModel,
+              image_width: u32,
+              image_height: u32,
+              samples_per_pixel: i32,
+              max_depth: i32) -> Film
+    {
+        let passes = samples_per_pixel.max(0) as u32;
+        let progress = RenderProgress::start(self.control(), u64::from(image_width * image_height) * u64::from(passes));
+        let materials = self.settings.records_aovs().then(|| MaterialIds::new(&scene));
+        let job = RenderJob {
+            settings: &self.settings,
+            scene: &scene,
+            camera,
+            image_width,
+            image_height,
+            samples_per_pixel: passes,
+            first_sample: 0,
+            max_depth,
+            progress: &progress,
+            interruptible: true,
+            materials: materials.as_ref(),
+        };
+        let emitters: Vec<usize> = emitting_lights(&scene)
+            .iter()
+            .enumerate()
+            .filter_map(|(k, &emits)| if emits { Some(k) } else { None })
+            .collect();
+
+        let mut pixels: Vec<PixelEstimate> = (0..image_width * image_height).map(|_| PixelEstimate::new(self.initial_radius)).collect();
+        let mut film = self.settings.film_region(0, 0, image_width, image_height);
+        let mut passes_done = 0;
+        for pass in 0..passes {
+            if progress.should_stop() {
+                break;
+            }
+            pixels.par_iter_mut().enumerate().for_each_init(
+                || job.create_sampler(),
+                |sampler, (index, pixel)| {
+                    let (x, y) = (index as u32 % image_width, index as u32 / image_width);
+                    pixel.visible = visible_point(&job, sampler.as_mut(), x, image_height - y - 1, pass, &mut pixel.direct, &mut pixel.first_hit);
+                },
+            );
+            for (index, pixel) in pixels.iter().enumerate() {
+                if let Some(aov) = &pixel.first_hit {
+                    let (x, y) = (index as u32 % image_width, index as u32 / image_width);
+                    film.add_aov_sample(x, y, aov);
+                }
+            }
+            let photons: Vec<Photon> = if emitters.is_empty() {
+                Vec::new()
+            } else {
+                (0..self.photons_per_pass)
+                    .into_par_iter()
+                    .map_init(
+                        || job.create_sampler(),
+                        |sampler, n| trace_photon(&job, &emitters, sampler.as_mut(), pass, n),
+                    )
+                    .collect::<Vec<Vec<Photon>>>()
+                    .concat()
+            };
+            let photon_map = PhotonMap::new(photons);
+            pixels.par_iter_mut().for_each(|pixel| pixel.gather(&photon_map, self.alpha));
+            progress.advance(u64::from(image_width * image_height));
+            passes_done += 1;
+        }
+        progress.finish();
+
+        if passes_done > 0 {
+            let photons_traced = f64::from(passes_done) * f64::from(self.photons_per_pass);
+            for (index, pixel) in pixels.iter().enumerate() {
+                let (x, y) = (index as u32 % image_width, index as u32 / image_width);
+                let radiance = pixel.direct / f64::from(passes_done) + pixel.flux / (photons_traced * PI * pixel.radius * pixel.radius);
+                film.set_pixel(x, y, radiance);
+                film.add_sample_count(x, y, passes_done);
+            }
+        }
+        film
+    }
+}
+
+/// First diffuse surface seen by a pixel in the current pass
+struct VisiblePoint {
+    rec: HitRecord,
+    ray: Ray,
+    /// Product of the scattering weights of the mirrors and glass in front of it
+    beta: Color,
+}
+
+/// Running estimate of one pixel
+struct PixelEstimate {
+    /// Sum over the passes of the light not carried by photons
+    direct: Color,
+    visible: Option<VisiblePoint>,
+    /// AOVs of this pass's camera ray, if recorded
+    first_hit: Option<AovSample>,
+    /// Photon flux reflected towards the camera, scaled to the current radius
+    flux: Color,
+    radius: f64,
+    /// Photons accumulated so far, after the reductions made when shrinking the radius
+    photons: f64,
+}
+
+impl PixelEstimate {
+    fn new(radius: f64) -> Self {
+        Self { direct: Color::default(), visible: None, first_hit: None, flux: Color::default(), radius, photons: 0. }
+    }
+
+    /// Adds the photons around this pass's visible point and shrinks the
+    /// radius so that only a fraction `alpha` of them is kept
+    fn gather(&mut self, photon_map: &PhotonMap, alpha: f64) {
+        let vp = match self.visible.take() {
+            Some(vp) => vp,
+            None => return,
+        };
+        let material = match &vp.rec.material {
+            Some(material) => material,
+            None => return,
+        };
+        let mut found = 0.;
+        let mut flux = Color::default();
+        photon_map.for_each_within(vp.rec.point, self.radius, |photon| {
+            found += 1.;
+            // eval includes the cosine at the surface, which the photon density already accounts for
+            let cos = vp.rec.normal.dot(photon.direction).abs();
+            if cos > 0. {
+                flux += material.eval(&vp.ray, &vp.rec, -photon.direction) / cos * photon.power;
+            }
+        });
+        if found == 0. {
+            return;
+        }
+        let photons = self.photons + alpha * found;
+        let radius = self.radius * (photons / (self.photons + found)).sqrt();
+        self.flux = (self.flux + vp.beta * flux) * (radius / self.radius).powi(2);
+        self.photons = photons;
+        self.radius = radius;
+    }
+}
+
+/// Follows the camera ray of pass `index` of pixel (i, j) through specular
+/// bounces, adding the light it sees directly to `direct` and recording the
+/// AOVs of its first hit in `first_hit`
+fn visible_point(job: &RenderJob, sampler: &mut dyn Sampler, i: u32, j: u32, index: u32, direct: &mut Color, first_hit: &mut Option<AovSample>) -> Option<VisiblePoint> {
+    let (_, _, ray) = job.camera_ray(sampler, i, j, index);
+    *first_hit = job.aov_sample(None);
+    let mut ray = ray?;
+    let mut beta = Color::new(1., 1., 1.);
+    for bounce in 0..job.max_depth.max(0) as u32 {
+        let hit = job.scene.hit(&ray, 0.001, f64::INFINITY);
+        if bounce == 0 {
+            *first_hit = job.aov_sample(hit.as_ref().map(|rec| (&ray, rec)));
+        }
+        let rec = match hit {
+            Some(rec) => rec,
+            None => {
+                *direct += beta * job.scene.background(&ray, true);
+                return None;
+            }
+        };
+        sampler.start_bounce(bounce);
+        if rec.shades_lights_directly() {
+            *direct += beta * rec.direct_light(&ray, job.scene);
+            // The environment is only lit directly, by one scattered ray
+            if let Some((scattered, attenuation)) = rec.scatter(&ray, sampler) {
+                if !job.scene.occluded(&scattered, 0.001, f64::INFINITY) {
+                    *direct += beta * attenuation * job.scene.background(&scattered, false);
+                }
+            }
+            return Some(VisiblePoint { rec, ray, beta });
+        }
+        let (scattered, attenuation) = rec.scatter(&ray, sampler)?;
+        beta = beta * attenuation;
+        ray = scattered;
+    }
+    None
+}
+
+/// Photon landed on a diffuse surface
+#[derive(Debug, Clone, Copy)]
+struct Photon {
+    point: Point3,
+    /// Unit direction the photon was travelling in
+    direction: Vec3,
+    power: Color,
+}
+
+/// Traces photon `index` of pass `pass` from one of the lights that start
+/// paths, chosen uniformly, and returns where it was stored
+fn trace_photon(job: &RenderJob, emitters: &[usize], sampler: &mut dyn Sampler, pass: u32, index: u32) -> Vec<Photon> {
+    let mut photons = Vec::new();
+    // Photons do not belong to a pixel; each pass samples as a pixel of its
+    // own on a row outside the image
+    sampler.start_pixel_sample(pass, u32::MAX, index);
+    sampler.set_dimension(LIGHT_PATH_DIMENSION);
+    let choice = ((sampler.get_1d() * emitters.len() as f64) as usize).min(emitters.len() - 1);
+    let light = &job.scene.lights()[emitters[choice]];
+    let emission = match light.sample_emission(sampler) {
+        Some(e) if e.pdf > 0. => e,
+        _ => return photons,
+    };
+    let power = emission.intensity * emitters.len() as f64 / emission.pdf;
+    let mut ray = Ray::new(emission.origin, emission.direction);
+    let mut throughput = Color::new(1., 1., 1.);
+    for bounce in 0..job.max_depth.max(0) as u32 {
+        let rec = match job.scene.hit(&ray, 0.001, f64::INFINITY) {
+            Some(rec) => rec,
+            None => break,
+        };
+        // Light arriving straight from the lights is found with shadow rays
+        if bounce > 0 && rec.shades_lights_directly() {
+            photons.push(Photon { point: rec.point, direction: ray.direction.unit(), power: power * throughput });
+        }
+        let base = LIGHT_PATH_DIMENSION + DIMENSIONS_PER_BOUNCE * (bounce + 1);
+        sampler.set_dimension(base);
+        let (scattered, attenuation) = match rec.scatter(&ray, sampler) {
+            Some(scatter) => scatter,
+            None => break,
+        };
+        throughput = throughput * attenuation;
+        if let Some(roulette) = &job.settings.russian_roulette {
+            if bounce + 1 >= roulette.min_bounces {
+                let survival = roulette.survival_probability(throughput);
+                sampler.set_dimension(base + ROULETTE_DIMENSION);
+                if sampler.get_1d() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+        ray = scattered;
+    }
+    photons
+}
+
+/// Balanced kd-tree of photons, stored as an array in which the middle
+/// photon of every range splits the rest of it along `axes` of that photon
+struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<u8>,
+}
+
+impl PhotonMap {
+    fn new(mut photons: Vec<Photon>) -> Self {
+        let mut axes = vec![0; photons.len()];
+        Self::build(&mut photons, &mut axes);
+        Self { photons, axes }
+    }
+
+    /// Splits along the axis of largest extent at the median photon
+    fn build(photons: &mut [Photon], axes: &mut [u8]) {
+        if photons.len() <= 1 {
+            return;
+        }
+        let mut min = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = -min;
+        for photon in photons.iter() {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(photon.point[axis]);
+                max[axis] = max[axis].max(photon.point[axis]);
+            }
+        }
+        let extent = max - min;
+        let axis = if extent.x() >= extent.y() && extent.x() >= extent.z() {
+            0
+        } else if extent.y() >= extent.z() {
+            1
+        } else {
+            2
+        };
+        let mid = photons.len() / 2;
+        photons.select_nth_unstable_by(mid, |a, b| a.point[axis].total_cmp(&b.point[axis]));
+        axes[mid] = axis as u8;
+        let (left, right) = photons.split_at_mut(mid);
+        let (left_axes, right_axes) = axes.split_at_mut(mid);
+        Self::build(left, left_axes);
+        Self::build(&mut right[1..], &mut right_axes[1..]);
+    }
+
+    /// Calls `f` with every photon at most `radius` from `point`
+    fn for_each_within<F: FnMut(&Photon)>(&self, point: Point3, radius: f64, mut f: F) {
+        self.visit(0, self.photons.len(), point, radius, &mut f);
+    }
+
+    fn visit<F: FnMut(&Photon)>(&self, start: usize, end: usize, point: Point3, radius: f64, f: &mut F) {
+        if start >= end {
+            return;
+        }
+        let mid = start + (end - start) / 2;
+        let photon = &self.photons[mid];
+        if (photon.point - point).length_squared() <= radius * radius {
+            f(photon);
+        }
+        let axis = usize::from(self.axes[mid]);
+        let offset = point[axis] - photon.point[axis];
+        if offset <= radius {
+            self.visit(start, mid, point, radius, f);
+        }
+        if offset >= -radius {
+            self.visit(mid + 1, end, point, radius, f);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderers::RayonRenderer;
+    use crate::{Camera, Environment, Lambertian, PointLight, Sphere};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_photon_map_finds_photons_in_radius() {
+        let photons: Vec<Photon> = (0..500)
+            .map(|i| {
+                let i = f64::from(i);
+                let point = Point3::new((i * 0.37).sin() * 3., (i * 0.91).cos() * 2., (i * 0.13).sin());
+                Photon { point, direction: Vec3::new(0., -1., 0.), power: Color::new(i, 0., 0.) }
+            })
+            .collect();
+        let center = Point3::new(0.5, -0.2, 0.1);
+        let mut expected: Vec<f64> = photons
+            .iter()
+            .filter(|p| (p.point - center).length() <= 0.8)
+            .map(|p| p.power.x())
+            .collect();
+        let mut found = Vec::new();
+        PhotonMap::new(photons).for_each_within(center, 0.8, |p| found.push(p.power.x()));
+        expected.sort_by(f64::total_cmp);
+        found.sort_by(f64::total_cmp);
+        assert!(!expected.is_empty());
+        assert_eq!(found, expected);
+    }
+
+    struct Black;
+    impl Environment for Black {
+        fn radiance(&self, _direction: Vec3) -> Color {
+            Color::default()
+        }
+    }
+
+    #[test]
+    fn test_converges_to_path_tracer_on_diffuse_scene() {
+        let scene = || {
+            let mut world = HittableList::new();
+            let grey = Arc::new(Lambertian::new(Color::new(0.7, 0.7, 0.7)));
+            world.add(Box::new(Sphere::new(Point3::new(0., -100.5, -1.), 100., grey.clone())));
+            world.add(Box::new(Sphere::new(Point3::new(0., 0., -1.), 0.5, grey)));
+            world.add_light(Arc::new(PointLight::new(Point3::new(1., 2., 0.), Color::new(4., 4., 4.))));
+            world.set_environment(Arc::new(Black));
+            world
+        };
+        let camera = Camera::new(Point3::new(0., 0.5, 1.), Point3::new(0., 0., -1.), Vec3::new(0., 1., 0.), 60., 4. / 3.);
+        let mean = |film: Film| {
+            let mut sum = 0.;
+            for y in 0..film.height() {
+                for x in 0..film.width() {
+                    sum += film.pixel(x, y).y();
+                }
+            }
+            sum / f64::from(film.width() * film.height())
+        };
+        let path_traced = mean(RayonRenderer::default().render_film(scene(), &camera, 16, 12, 256, 8));
+        let photon_mapped = mean(PhotonMapRenderer::default().with_photons_per_pass(5000).render_film(scene(), &camera, 16, 12, 16, 8));
+        assert!((photon_mapped - path_traced).abs() < 0.05 * path_traced, "{} vs {}", photon_mapped, path_traced);
+    }
+}
@@ -0,0 +1,447 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// First sample dimension of the sub-pixel position
+pub const PIXEL_DIMENSION: u32 = 0;
+/// First sample dimension of the lens position
+pub const LENS_DIMENSION: u32 = 2;
+/// First sample dimension used by the scatter decision of the first bounce
+pub const BOUNCE_DIMENSION: u32 = 4;
+/// Number of dimensions reserved for each bounce
+pub const DIMENSIONS_PER_BOUNCE: u32 = 4;
+/// First sample dimension of the distance and direction sampling in media,
+/// in blocks of `DIMENSIONS_PER_BOUNCE` per bounce like the surface ones
+pub const MEDIUM_DIMENSION: u32 = 1 << 15;
+/// First sample dimension of the light paths of bidirectional methods, well
+/// past the dimensions of any camera path
+pub const LIGHT_PATH_DIMENSION: u32 = 1 << 16;
+/// Dimension within a bounce of the Russian roulette decision, after those
+/// the materials use to scatter
+pub const ROULETTE_DIMENSION: u32 = 3;
+
+/// Source of the sample values consumed while tracing a camera sample
+///
+/// Values are a pure function of the seed, the pixel, the sample index and the
+/// sample dimension, so they do not depend on the order in which (or the thread
+/// on which) samples are evaluated. Renderers call `start_pixel_sample` before
+/// each camera sample and `start_bounce` before each scatter decision, so that
+/// a given dimension always means the same thing across samples.
+pub trait Sampler {
+    /// Prepares the sampler for the `index`th sample of pixel (x, y)
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32);
+    /// Moves to a given dimension of the current sample
+    fn set_dimension(&mut self, dimension: u32);
+    /// Next value in [0, 1), consuming one dimension
+    fn get_1d(&mut self) -> f64;
+    /// Next pair of values in [0, 1)^2, consuming two dimensions
+    fn get_2d(&mut self) -> (f64, f64);
+
+    /// Moves to the dimensions reserved for the given bounce
+    fn start_bounce(&mut self, bounce: u32) {
+        self.set_dimension(BOUNCE_DIMENSION + DIMENSIONS_PER_BOUNCE * bounce);
+    }
+    /// Uniform number in [min, max)
+    fn gen_range(&mut self, min: f64, max: f64) -> f64 {
+        min + (max - min) * self.get_1d()
+    }
+}
+
+/// Sampling strategy used by the renderers
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SamplerKind {
+    #[default]
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn create(&self, seed: u64, samples_per_pixel: u32) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(seed, samples_per_pixel)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(format!("unknown sampler '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for SamplerKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SamplerKind::Independent => "independent",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Position of a sampler within the (pixel, sample index, dimension) space
+#[derive(Debug, Clone, Copy, Default)]
+struct SampleState {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn new(seed: u64) -> Self {
+        Self { seed: mix(seed), ..Self::default() }
+    }
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel = mix(self.seed ^ mix((u64::from(x) << 32) | u64::from(y)));
+        self.index = index;
+        self.dimension = 0;
+    }
+    /// Hash of the current pixel and the given dimension, shared by all sample indices
+    fn pixel_hash(&self, dimension: u32) -> u64 {
+        mix(self.pixel ^ u64::from(dimension))
+    }
+    /// Hash of the current pixel, sample index and the given dimension
+    fn sample_hash(&self, dimension: u32) -> u64 {
+        mix(self.pixel_hash(dimension) ^ (u64::from(self.index) << 32))
+    }
+    fn next_dimension(&mut self, count: u32) -> u32 {
+        let dimension = self.dimension;
+        self.dimension = self.dimension.wrapping_add(count);
+        dimension
+    }
+}
+
+/// Uniform random values, independent across dimensions and samples
+pub struct IndependentSampler {
+    state: SampleState,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        Self { state: SampleState::new(seed) }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start_pixel_sample(x, y, index);
+    }
+    fn set_dimension(&mut self, dimension: u32) {
+        self.state.dimension = dimension;
+    }
+    fn get_1d(&mut self) -> f64 {
+        let d = self.state.next_dimension(1);
+        to_unit_f64(self.state.sample_hash(d))
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+/// Jittered samples, one per stratum of each 1D or 2D dimension
+///
+/// Strata are assigned to sample indices through a per-pixel, per-dimension
+/// permutation, so each group of `samples_per_pixel` consecutive samples
+/// covers every stratum once.
+pub struct StratifiedSampler {
+    state: SampleState,
+    samples_per_pixel: u32,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples_per_pixel: u32) -> Self {
+        Self {
+            state: SampleState::new(seed),
+            samples_per_pixel: samples_per_pixel.max(1),
+        }
+    }
+    fn stratum(&self, dimension: u32, n_strata: u32) -> u32 {
+        let n = self.samples_per_pixel;
+        let round = self.state.index / n;
+        let p = mix(self.state.pixel_hash(dimension) ^ u64::from(round)) as u32;
+        permute(self.state.index % n, n_strata, p)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start_pixel_sample(x, y, index);
+    }
+    fn set_dimension(&mut self, dimension: u32) {
+        self.state.dimension = dimension;
+    }
+    fn get_1d(&mut self) -> f64 {
+        let d = self.state.next_dimension(1);
+        let n = self.samples_per_pixel;
+        let jitter = to_unit_f64(self.state.sample_hash(d));
+        (self.stratum(d, n) as f64 + jitter) / n as f64
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        let d = self.state.next_dimension(2);
+        let n = self.samples_per_pixel;
+        let nx = (n as f64).sqrt().ceil() as u32;
+        let ny = n.div_ceil(nx);
+        let s = self.stratum(d, nx * ny);
+        let jx = to_unit_f64(self.state.sample_hash(d));
+        let jy = to_unit_f64(self.state.sample_hash(d + 1));
+        (((s % nx) as f64 + jx) / nx as f64, ((s / nx) as f64 + jy) / ny as f64)
+    }
+}
+
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83,
+    89, 97, 101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179,
+    181, 191, 193, 197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277,
+    281, 283, 293, 307, 311,
+];
+
+/// Halton sequence with a per-pixel Cranley-Patterson rotation
+///
+/// Dimensions beyond the prime table fall back to independent values.
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        Self { state: SampleState::new(seed) }
+    }
+    fn sample(&self, dimension: u32) -> f64 {
+        match PRIMES.get(dimension as usize) {
+            Some(&base) => {
+                let offset = to_unit_f64(self.state.pixel_hash(dimension));
+                let x = radical_inverse(base, self.state.index) + offset;
+                if x >= 1. { x - 1. } else { x }
+            }
+            None => to_unit_f64(self.state.sample_hash(dimension)),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start_pixel_sample(x, y, index);
+    }
+    fn set_dimension(&mut self, dimension: u32) {
+        self.state.dimension = dimension;
+    }
+    fn get_1d(&mut self) -> f64 {
+        let d = self.state.next_dimension(1);
+        self.sample(d)
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        let d = self.state.next_dimension(2);
+        (self.sample(d), self.sample(d + 1))
+    }
+}
+
+/// Owen-scrambled Sobol points
+///
+/// Every 1D or 2D request is drawn from the first two Sobol dimensions, which
+/// form a (0, 2)-sequence, with the sample index shuffled and the values
+/// scrambled independently per pixel and dimension (Burley 2020).
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        Self { state: SampleState::new(seed) }
+    }
+    fn shuffled_index(&self, dimension: u32) -> u32 {
+        nested_uniform_scramble(self.state.index, self.state.pixel_hash(dimension) as u32)
+    }
+    fn scrambled(&self, x: u32, dimension: u32) -> f64 {
+        let seed = (self.state.pixel_hash(dimension) >> 32) as u32;
+        f64::from(nested_uniform_scramble(x, seed)) / 4_294_967_296.
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start_pixel_sample(x, y, index);
+    }
+    fn set_dimension(&mut self, dimension: u32) {
+        self.state.dimension = dimension;
+    }
+    fn get_1d(&mut self) -> f64 {
+        let d = self.state.next_dimension(1);
+        let i = self.shuffled_index(d);
+        self.scrambled(i.reverse_bits(), d)
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        let d = self.state.next_dimension(2);
+        let i = self.shuffled_index(d);
+        (self.scrambled(i.reverse_bits(), d), self.scrambled(sobol_second_dimension(i), d + 1))
+    }
+}
+
+fn radical_inverse(base: u32, mut index: u32) -> f64 {
+    let inv_base = 1. / f64::from(base);
+    let mut inv_base_n = 1.;
+    let mut reversed = 0u64;
+    while index > 0 {
+        let next = index / base;
+        let digit = index - next * base;
+        reversed = reversed * u64::from(base) + u64::from(digit);
+        inv_base_n *= inv_base;
+        index = next;
+    }
+    (reversed as f64 * inv_base_n).min(1. - f64::EPSILON)
+}
+
+/// Second Sobol dimension, generated by the primitive polynomial x + 1
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut v = 1u32 << 31;
+    let mut result = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// Kensler's hash-based permutation of [0, len)
+fn permute(mut i: u32, len: u32, p: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % len
+}
+
+/// SplitMix64 finalizer, used to decorrelate neighbouring seeds
+pub fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn to_unit_f64(h: u64) -> f64 {
+    (h >> 11) as f64 * (1. / (1u64 << 53) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    #[test]
+    fn test_pixel_samples_are_reproducible() {
+        for kind in KINDS.iter() {
+            let mut a = kind.create(7, 16);
+            let mut b = kind.create(7, 16);
+            a.start_pixel_sample(10, 20, 3);
+            b.start_pixel_sample(10, 20, 3);
+            let xa: Vec<f64> = (0..8).map(|_| a.get_1d()).collect();
+            let xb: Vec<f64> = (0..8).map(|_| b.get_1d()).collect();
+            assert_eq!(xa, xb, "{}", kind);
+            a.start_pixel_sample(20, 10, 3);
+            let xc: Vec<f64> = (0..8).map(|_| a.get_1d()).collect();
+            assert_ne!(xa, xc, "{}", kind);
+        }
+    }
+    #[test]
+    fn test_dimensions_are_consistent() {
+        for kind in KINDS.iter() {
+            let mut a = kind.create(1, 16);
+            a.start_pixel_sample(0, 0, 5);
+            a.get_2d();
+            a.start_bounce(2);
+            let x = a.get_2d();
+            a.start_pixel_sample(0, 0, 5);
+            a.start_bounce(2);
+            assert_eq!(a.get_2d(), x, "{}", kind);
+        }
+    }
+    #[test]
+    fn test_stratified_covers_every_stratum() {
+        let n = 16;
+        for kind in &[SamplerKind::Stratified, SamplerKind::Sobol] {
+            let mut sampler = kind.create(3, n);
+            let mut cells = vec![0; n as usize];
+            for i in 0..n {
+                sampler.start_pixel_sample(4, 2, i);
+                let (u, v) = sampler.get_2d();
+                assert!((0. ..1.).contains(&u) && (0. ..1.).contains(&v));
+                cells[(u * 4.) as usize + 4 * (v * 4.) as usize] += 1;
+            }
+            assert!(cells.iter().all(|&c| c == 1), "{} {:?}", kind, cells);
+        }
+    }
+    #[test]
+    fn test_radical_inverse() {
+        assert_eq!(radical_inverse(2, 1), 0.5);
+        assert_eq!(radical_inverse(2, 3), 0.75);
+        assert!((radical_inverse(3, 1) - 1. / 3.).abs() < 1e-12);
+    }
+    #[test]
+    fn test_permute_is_bijection() {
+        for &len in &[1u32, 7, 16, 33] {
+            let mut seen: Vec<u32> = (0..len).map(|i| permute(i, len, 0xdead_beef)).collect();
+            seen.sort();
+            assert_eq!(seen, (0..len).collect::<Vec<_>>());
+        }
+    }
+}
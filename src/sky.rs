@@ -0,0 +1,191 @@
+use crate::{Color, SunLight, Vec3};
+use std::f64::consts::PI;
+
+/// Light arriving from infinitely far away along rays that miss the scene
+pub trait Environment {
+    /// Radiance seen looking in `direction`, which need not be normalized
+    fn radiance(&self, direction: Vec3) -> Color;
+}
+
+/// White horizon blending into a light blue zenith
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GradientSky;
+
+impl Environment for GradientSky {
+    fn radiance(&self, direction: Vec3) -> Color {
+        let unit_direction = direction.unit();
+        // Convert y-component (-1 to 1) to blue color
+        let t = 0.5 * (unit_direction.y() + 1.0);
+        (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
+    }
+}
+
+/// Angular radius of the sun seen from the earth
+pub const SUN_ANGULAR_RADIUS: f64 = 0.2665;
+
+/// Luminance of the sun above the atmosphere, in kcd/m²
+const SUN_LUMINANCE: f64 = 2.0e6;
+
+/// Clear daylight sky of Preetham, Shirley and Smits (1999)
+///
+/// The sky luminance and chromaticity come from the Perez distribution fitted
+/// to the sun position and atmospheric turbidity, 2 for a very clear sky to
+/// around 10 for haze. `sun_light` gives the matching sun disk, attenuated by
+/// the same atmosphere; the sky itself does not include it. Directions below
+/// the horizon see the sky at the horizon.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhysicalSky {
+    /// Degrees above the horizon
+    sun_elevation: f64,
+    /// Degrees clockwise from -z towards +x
+    sun_azimuth: f64,
+    turbidity: f64,
+    /// Scale from kcd/m² to scene radiance
+    pub intensity: f64,
+    sun_direction: Vec3,
+    /// Zenith value and Perez coefficients of luminance and x and y chromaticity
+    perez: [(f64, [f64; 5]); 3],
+}
+
+impl PhysicalSky {
+    pub fn new(sun_elevation: f64, sun_azimuth: f64, turbidity: f64) -> Self {
+        let (elevation, azimuth) = (sun_elevation.to_radians(), sun_azimuth.to_radians());
+        let sun_direction = Vec3::new(elevation.cos() * azimuth.sin(), elevation.sin(), -elevation.cos() * azimuth.cos());
+        let t = turbidity;
+        // The fit is only defined with the sun above the horizon
+        let theta_s = (PI / 2. - elevation).clamp(0., PI / 2.);
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_s);
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.);
+        let chromaticity = |c: [[f64; 4]; 3]| {
+            let poly = |k: [f64; 4]| ((k[0] * theta_s + k[1]) * theta_s + k[2]) * theta_s + k[3];
+            t * t * poly(c[0]) + t * poly(c[1]) + poly(c[2])
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+        let coefficients = |c: [[f64; 2]; 5]| {
+            let mut out = [0.; 5];
+            for (o, k) in out.iter_mut().zip(c.iter()) {
+                *o = k[0] * t + k[1];
+            }
+            out
+        };
+        let perez = [
+            (zenith_luminance, coefficients([[0.1787, -1.4630], [-0.3554, 0.4275], [-0.0227, 5.3251], [0.1206, -2.5771], [-0.0670, 0.3703]])),
+            (zenith_x, coefficients([[-0.0193, -0.2592], [-0.0665, 0.0008], [-0.0004, 0.2125], [-0.0641, -0.8989], [-0.0033, 0.0452]])),
+            (zenith_y, coefficients([[-0.0167, -0.2608], [-0.0950, 0.0092], [-0.0079, 0.2102], [-0.0441, -1.6537], [-0.0109, 0.0529]])),
+        ];
+        Self { sun_elevation, sun_azimuth, turbidity, intensity: 0.05, sun_direction, perez }
+    }
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn sun_elevation(&self) -> f64 {
+        self.sun_elevation
+    }
+    pub fn sun_azimuth(&self) -> f64 {
+        self.sun_azimuth
+    }
+    pub fn turbidity(&self) -> f64 {
+        self.turbidity
+    }
+    /// Unit direction towards the sun
+    pub fn sun_direction(&self) -> Vec3 {
+        self.sun_direction
+    }
+
+    /// Radiance of the sun disk after Rayleigh and aerosol extinction along
+    /// its path through the atmosphere; black once the sun has set
+    pub fn sun_radiance(&self) -> Color {
+        if self.sun_elevation < 0. {
+            return Color::default();
+        }
+        let zenith_deg = 90. - self.sun_elevation;
+        // Kasten's relative air mass
+        let air_mass = 1. / (zenith_deg.to_radians().cos() + 0.15 * (93.885 - zenith_deg).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        // Wavelengths in micrometers standing in for the red, green and blue channels
+        let transmittance = |lambda: f64| {
+            let rayleigh = -0.008735 * lambda.powf(-4.08) * air_mass;
+            let aerosol = -beta * lambda.powf(-1.3) * air_mass;
+            (rayleigh + aerosol).exp()
+        };
+        self.intensity * SUN_LUMINANCE * Color::new(transmittance(0.65), transmittance(0.57), transmittance(0.475))
+    }
+
+    /// Sun disk to add to the scene's lights along with this sky
+    pub fn sun_light(&self) -> SunLight {
+        SunLight::new(self.sun_direction, self.sun_radiance(), SUN_ANGULAR_RADIUS)
+    }
+}
+
+impl Environment for PhysicalSky {
+    fn radiance(&self, direction: Vec3) -> Color {
+        let d = direction.unit();
+        // Keep 1 / cos(theta) finite at and below the horizon
+        let cos_theta = d.y().max(0.01);
+        let cos_gamma = d.dot(self.sun_direction).clamp(-1., 1.);
+        let gamma = cos_gamma.acos();
+        let theta_s = (PI / 2. - self.sun_elevation.to_radians()).clamp(0., PI / 2.);
+        let perez = |k: &[f64; 5], cos_theta: f64, gamma: f64| {
+            (1. + k[0] * (k[1] / cos_theta).exp()) * (1. + k[2] * (k[3] * gamma).exp() + k[4] * gamma.cos().powi(2))
+        };
+        let [luminance, x, y] = [0, 1, 2].map(|i| {
+            let (zenith, k) = &self.perez[i];
+            zenith * perez(k, cos_theta, gamma) / perez(k, 1., theta_s)
+        });
+        if y <= 0. {
+            return Color::default();
+        }
+        // xyY to linear sRGB
+        let (cx, cy, cz) = (x * luminance / y, luminance, (1. - x - y) * luminance / y);
+        let rgb = Color::new(
+            3.2406 * cx - 1.5372 * cy - 0.4986 * cz,
+            -0.9689 * cx + 1.8758 * cy + 0.0415 * cz,
+            0.0557 * cx - 0.2040 * cy + 1.0570 * cz,
+        );
+        self.intensity * Color::new(rgb.x().max(0.), rgb.y().max(0.), rgb.z().max(0.))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{luminance, Light};
+    #[test]
+    fn test_sky_brightest_near_sun() {
+        let sky = PhysicalSky::new(30., 90., 3.);
+        let towards_sun = luminance(sky.radiance(Vec3::new(1., 0.7, 0.)));
+        let away_from_sun = luminance(sky.radiance(Vec3::new(-1., 0.7, 0.)));
+        assert!(towards_sun > away_from_sun && away_from_sun > 0.);
+        // Clear skies are blue away from the sun
+        let zenith = sky.radiance(Vec3::new(0., 1., 0.));
+        assert!(zenith.z() > zenith.x());
+    }
+    #[test]
+    fn test_low_sun_is_red_and_dim() {
+        let noon = PhysicalSky::new(80., 0., 3.).sun_radiance();
+        let sunset = PhysicalSky::new(3., 0., 3.).sun_radiance();
+        assert!(sunset.x() / sunset.z() > noon.x() / noon.z());
+        assert!(luminance(sunset) < luminance(noon));
+        assert_eq!(PhysicalSky::new(-5., 0., 3.).sun_radiance(), Color::default());
+    }
+    #[test]
+    fn test_sun_light_matches_disk() {
+        let sky = PhysicalSky::new(45., 0., 3.);
+        let sun = sky.sun_light();
+        assert_eq!(sun.emitted(sky.sun_direction()), sky.sun_radiance());
+        assert_eq!(sun.emitted(Vec3::new(0., 1., 0.)), Color::default());
+        let sample = sun.sample(Vec3::default()).unwrap();
+        assert!((sample.direction - sky.sun_direction()).length() < 1e-12);
+    }
+}
@@ -1,5 +1,6 @@
 use std::sync::Arc;
-use crate::{HitRecord, Hittable, Point3, Ray};
+use crate::{HitRecord, Hittable, Point3, Ray, Vec3};
+use std::f64::consts::PI;
 use crate::Material;
 pub struct SimpleSphere {
     center: Point3,
@@ -19,7 +20,7 @@ pub struct Sphere {
 impl Sphere
 {
     pub fn new(center: Point3, radius: f64, material: Arc<dyn Material + Sync + Send>) -> Self {
-        Self { center, radius, material: material }
+        Self { center, radius, material }
     }
 }
 
@@ -39,7 +40,8 @@ impl Hittable for Sphere {
                 // Ray hitting outside sphere
                 let point = r.at(temp);
                 let outward_normal = (point - self.center) / self.radius;
-                let hr = HitRecord::new_with_material(outward_normal, &r, temp, point, self.material.clone());
+                let mut hr = HitRecord::new_with_material(outward_normal, r, temp, point, self.material.clone());
+                (hr.u, hr.v) = sphere_uv(outward_normal);
                 return Some(hr);
             }
             let temp = (-half_b + d_root) / a;
@@ -47,11 +49,18 @@ impl Hittable for Sphere {
                 // Ray hitting inside sphere
                 let point = r.at(temp);
                 let outward_normal = (point - self.center) / self.radius;
-                let hr = HitRecord::new_with_material(outward_normal, &r, temp, point, self.material.clone());
+                let mut hr = HitRecord::new_with_material(outward_normal, r, temp, point, self.material.clone());
+                (hr.u, hr.v) = sphere_uv(outward_normal);
                 return Some(hr);
             }
         }
-        return None;
+        None
+    }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        sphere_occludes(self.center, self.radius, r, t_min, t_max)
+    }
+    fn collect_materials(&self, materials: &mut Vec<Arc<dyn Material + Sync + Send>>) {
+        materials.push(self.material.clone());
     }
 }
 
@@ -72,7 +81,8 @@ impl Hittable for SimpleSphere {
                 // Ray hitting outside sphere
                 let point = r.at(temp);
                 let outward_normal = (point - self.center) / self.radius;
-                let hr = HitRecord::new(outward_normal, &r, temp, point);
+                let mut hr = HitRecord::new(outward_normal, r, temp, point);
+                (hr.u, hr.v) = sphere_uv(outward_normal);
                 return Some(hr);
             }
             let temp = (-half_b + d_root) / a;
@@ -80,10 +90,65 @@ impl Hittable for SimpleSphere {
                 // Ray hitting inside sphere
                 let point = r.at(temp);
                 let outward_normal = (point - self.center) / self.radius;
-                let hr = HitRecord::new(outward_normal, &r, temp, point);
+                let mut hr = HitRecord::new(outward_normal, r, temp, point);
+                (hr.u, hr.v) = sphere_uv(outward_normal);
                 return Some(hr);
             }
         }
-        return None;
+        None
+    }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        sphere_occludes(self.center, self.radius, r, t_min, t_max)
+    }
+}
+
+/// Longitude and latitude of a point on the unit sphere, scaled to [0, 1];
+/// u grows counterclockwise around y from -x, v from the bottom to the top
+fn sphere_uv(p: Vec3) -> (f64, f64) {
+    let theta = (-p.y()).clamp(-1., 1.).acos();
+    let phi = (-p.z()).atan2(p.x()) + PI;
+    (phi / (2. * PI), theta / PI)
+}
+
+/// Whether the ray enters or leaves the sphere between `t_min` and `t_max`
+fn sphere_occludes(center: Point3, radius: f64, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    let oc = r.origin - center;
+    let a = r.direction.length_squared();
+    let half_b = oc.dot(r.direction);
+    let c = oc.length_squared() - radius * radius;
+    let discriminant = half_b * half_b - a * c;
+    if discriminant <= 0. {
+        return false;
+    }
+    let d_root = discriminant.sqrt();
+    let near = (-half_b - d_root) / a;
+    let far = (-half_b + d_root) / a;
+    (near < t_max && near > t_min) || (far < t_max && far > t_min)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{materials::Lambertian, Color, HittableList};
+    #[test]
+    fn test_occluded_matches_hit() {
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(Point3::new(0., 0., -2.), 0.5, Arc::new(Lambertian::new(Color::default())))));
+        world.add(Box::new(SimpleSphere::new(Point3::new(1., 0., -4.), 1.)));
+        for i in 0..200 {
+            let x = (i % 20) as f64 / 10. - 1.;
+            let t_max = (i / 20) as f64 * 0.5;
+            let r = Ray::new(Point3::default(), Vec3::new(x, 0.1, -1.));
+            assert_eq!(world.occluded(&r, 0.001, t_max), world.hit(&r, 0.001, t_max).is_some());
+        }
+    }
+    #[test]
+    fn test_sphere_uv() {
+        let sphere = SimpleSphere::new(Point3::new(0., 0., -3.), 1.);
+        // Seen from the +z side the sphere shows u = 0.25 in the middle
+        let rec = sphere.hit(&Ray::new(Point3::default(), Vec3::new(0., 0., -1.)), 0.001, f64::INFINITY).unwrap();
+        assert!((rec.u - 0.25).abs() < 1e-9 && (rec.v - 0.5).abs() < 1e-9);
+        let top = sphere.hit(&Ray::new(Point3::new(0., 5., -3.), Vec3::new(0., -1., 0.)), 0.001, f64::INFINITY).unwrap();
+        assert!((top.v - 1.).abs() < 1e-9);
     }
 }
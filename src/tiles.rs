@@ -0,0 +1,147 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Rectangular block of pixels, in image coordinates (rows top to bottom)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub x0: u32,
+    pub y0: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> {
+        let (x0, y0, w, h) = (self.x0, self.y0, self.width, self.height);
+        (y0..y0 + h).flat_map(move |y| (x0..x0 + w).map(move |x| (x, y)))
+    }
+}
+
+/// Order in which tiles are handed out to the workers
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TileOrder {
+    /// Left to right, top to bottom
+    #[default]
+    Scanline,
+    /// Outwards from the center of the image
+    Spiral,
+    /// Along a Hilbert curve, keeping consecutive tiles adjacent
+    Hilbert,
+}
+
+/// Splits an image into tiles of at most `tile_size` pixels square, in the given order
+pub fn make_tiles(image_width: u32, image_height: u32, tile_size: u32, order: TileOrder) -> Vec<Tile> {
+    let tile_size = tile_size.max(1);
+    let nx = image_width.div_ceil(tile_size);
+    let ny = image_height.div_ceil(tile_size);
+    let mut coords: Vec<(u32, u32)> = (0..ny).flat_map(|ty| (0..nx).map(move |tx| (tx, ty))).collect();
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => {
+            let cx = (nx as f64 - 1.) / 2.;
+            let cy = (ny as f64 - 1.) / 2.;
+            let key = |&(tx, ty): &(u32, u32)| {
+                let dx = tx as f64 - cx;
+                let dy = ty as f64 - cy;
+                let ring = dx.abs().max(dy.abs()).round();
+                (ring, dy.atan2(dx))
+            };
+            coords.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+        }
+        TileOrder::Hilbert => {
+            let n = nx.max(ny).next_power_of_two();
+            coords.sort_by_key(|&(tx, ty)| hilbert_index(n, tx, ty));
+        }
+    }
+    coords
+        .into_iter()
+        .map(|(tx, ty)| {
+            let x0 = tx * tile_size;
+            let y0 = ty * tile_size;
+            Tile {
+                x0,
+                y0,
+                width: tile_size.min(image_width - x0),
+                height: tile_size.min(image_height - y0),
+            }
+        })
+        .collect()
+}
+
+/// Distance along the Hilbert curve filling an n x n grid (n a power of two)
+fn hilbert_index(n: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut d = 0u64;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = u32::from(x & s > 0);
+        let ry = u32::from(y & s > 0);
+        d += u64::from(s) * u64::from(s) * u64::from((3 * rx) ^ ry);
+        // Rotate the quadrant so the curve stays continuous
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x &= s - 1;
+        y &= s - 1;
+        s /= 2;
+    }
+    d
+}
+
+impl FromStr for TileOrder {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(format!("unknown tile order '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for TileOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TileOrder::Scanline => "scanline",
+            TileOrder::Spiral => "spiral",
+            TileOrder::Hilbert => "hilbert",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_tiles_cover_image_once() {
+        for order in &[TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            let (w, h) = (100, 37);
+            let mut covered = vec![0; (w * h) as usize];
+            for tile in make_tiles(w, h, 16, *order) {
+                for (x, y) in tile.pixels() {
+                    covered[(y * w + x) as usize] += 1;
+                }
+            }
+            assert!(covered.iter().all(|&c| c == 1), "{}", order);
+        }
+    }
+    #[test]
+    fn test_hilbert_tiles_are_adjacent() {
+        let tiles = make_tiles(64, 64, 8, TileOrder::Hilbert);
+        for pair in tiles.windows(2) {
+            let dx = (pair[0].x0 as i64 - pair[1].x0 as i64).abs();
+            let dy = (pair[0].y0 as i64 - pair[1].y0 as i64).abs();
+            assert_eq!(dx + dy, 8);
+        }
+    }
+    #[test]
+    fn test_spiral_starts_at_center() {
+        let tiles = make_tiles(90, 90, 30, TileOrder::Spiral);
+        assert_eq!((tiles[0].x0, tiles[0].y0), (30, 30));
+    }
+}
@@ -0,0 +1,191 @@
+use crate::{clamp, Color};
+use std::fmt;
+use std::str::FromStr;
+
+/// Curve used to compress linear (HDR) radiance into the displayable [0, 1] range
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMapOperator {
+    /// Hard clip at 1.0
+    Clamp,
+    /// c / (1 + c)
+    Reinhard,
+    /// Hable's "Uncharted 2" filmic curve
+    Filmic,
+    /// Narkowicz's fit of the ACES reference rendering transform
+    Aces,
+}
+
+/// Encoding applied to tone mapped values before quantizing to 8 bits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransferFunction {
+    Linear,
+    /// Square-root approximation used throughout the book
+    Gamma2,
+    /// Piecewise sRGB OETF
+    Srgb,
+}
+
+/// Converts linear radiance to display-referred 8-bit color
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneMap {
+    pub operator: ToneMapOperator,
+    /// Exposure adjustment in stops (radiance is scaled by 2^exposure)
+    pub exposure: f64,
+    pub transfer: TransferFunction,
+}
+
+impl Default for ToneMap {
+    // Equivalent to `Vec3::to_rgb_scaled_gamma2`
+    fn default() -> Self {
+        Self {
+            operator: ToneMapOperator::Clamp,
+            exposure: 0.,
+            transfer: TransferFunction::Gamma2,
+        }
+    }
+}
+
+impl ToneMap {
+    pub fn new(operator: ToneMapOperator, exposure: f64, transfer: TransferFunction) -> Self {
+        Self { operator, exposure, transfer }
+    }
+
+    /// Maps linear radiance to encoded values in [0, 1]
+    pub fn map(&self, c: Color) -> Color {
+        let scale = 2f64.powf(self.exposure);
+        let mut out = Color::default();
+        for i in 0..3 {
+            let x = self.operator.apply(c[i] * scale);
+            out[i] = self.transfer.encode(clamp(x, 0., 1.));
+        }
+        out
+    }
+
+    pub fn to_rgb(&self, c: Color) -> [u8; 3] {
+        let m = self.map(c);
+        let ir = (256f64 * clamp(m.x(), 0., 0.999)) as u8;
+        let ig = (256f64 * clamp(m.y(), 0., 0.999)) as u8;
+        let ib = (256f64 * clamp(m.z(), 0., 0.999)) as u8;
+        [ir, ig, ib]
+    }
+}
+
+impl ToneMapOperator {
+    pub fn apply(&self, x: f64) -> f64 {
+        let x = x.max(0.);
+        match self {
+            ToneMapOperator::Clamp => x,
+            ToneMapOperator::Reinhard => x / (1. + x),
+            ToneMapOperator::Filmic => {
+                const WHITE_POINT: f64 = 11.2;
+                hable(2. * x) / hable(WHITE_POINT)
+            }
+            ToneMapOperator::Aces => {
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                (x * (a * x + b)) / (x * (c * x + d) + e)
+            }
+        }
+    }
+}
+
+fn hable(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+impl TransferFunction {
+    pub fn encode(&self, x: f64) -> f64 {
+        match self {
+            TransferFunction::Linear => x,
+            TransferFunction::Gamma2 => x.sqrt(),
+            TransferFunction::Srgb => {
+                if x <= 0.003_130_8 {
+                    12.92 * x
+                } else {
+                    1.055 * x.powf(1. / 2.4) - 0.055
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for ToneMapOperator {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "clamp" => Ok(ToneMapOperator::Clamp),
+            "reinhard" => Ok(ToneMapOperator::Reinhard),
+            "filmic" => Ok(ToneMapOperator::Filmic),
+            "aces" => Ok(ToneMapOperator::Aces),
+            _ => Err(format!("unknown tone map operator '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for ToneMapOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ToneMapOperator::Clamp => "clamp",
+            ToneMapOperator::Reinhard => "reinhard",
+            ToneMapOperator::Filmic => "filmic",
+            ToneMapOperator::Aces => "aces",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for TransferFunction {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "linear" => Ok(TransferFunction::Linear),
+            "gamma2" => Ok(TransferFunction::Gamma2),
+            "srgb" => Ok(TransferFunction::Srgb),
+            _ => Err(format!("unknown transfer function '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for TransferFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TransferFunction::Linear => "linear",
+            TransferFunction::Gamma2 => "gamma2",
+            TransferFunction::Srgb => "srgb",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_default_matches_gamma2() {
+        let c = Color::new(0.25, 0.5, 4.0);
+        let tm = ToneMap::default();
+        assert_eq!(tm.to_rgb(c), (c * 2.).to_rgb_scaled_gamma2(2));
+    }
+    #[test]
+    fn test_srgb_is_continuous() {
+        let srgb = TransferFunction::Srgb;
+        let x = 0.003_130_8;
+        assert!((srgb.encode(x) - srgb.encode(x + 1e-9)).abs() < 1e-6);
+        assert!((srgb.encode(1.0) - 1.0).abs() < 1e-9);
+    }
+    #[test]
+    fn test_operators_are_monotonic() {
+        for op in &[ToneMapOperator::Reinhard, ToneMapOperator::Filmic, ToneMapOperator::Aces] {
+            assert!(op.apply(0.).abs() < 1e-9, "{} does not map black to black", op);
+            assert_eq!(op.apply(-1.), op.apply(0.));
+            let mut prev = 0.;
+            for i in 1..100 {
+                let y = op.apply(i as f64 * 0.1);
+                assert!(y > prev, "{} is not increasing", op);
+                prev = y;
+            }
+            let white = ToneMap::new(*op, 0., TransferFunction::Linear).map(Color::new(1e3, 1e3, 1e3));
+            assert!(white.x() > 0.9 && white.x() <= 1.0);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::{HitRecord, Hittable, Material, Ray, Vec3};
+use std::sync::Arc;
+
+/// Similarity transform: uniform scale, then rotation, then translation
+///
+/// Rotation angles are in degrees about the world x, y and z axes, applied in
+/// that order; everything happens about the world origin.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Vec3,
+    pub scale: f64,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self { translation: Vec3::default(), rotation: Vec3::default(), scale: 1. }
+    }
+}
+
+impl Transform {
+    pub fn translate(translation: Vec3) -> Self {
+        Self { translation, ..Self::default() }
+    }
+
+    /// Rows of the rotation matrix Rz * Ry * Rx
+    fn rotation_matrix(&self) -> [Vec3; 3] {
+        let (sx, cx) = self.rotation.x().to_radians().sin_cos();
+        let (sy, cy) = self.rotation.y().to_radians().sin_cos();
+        let (sz, cz) = self.rotation.z().to_radians().sin_cos();
+        [
+            Vec3::new(cz * cy, cz * sy * sx - sz * cx, cz * sy * cx + sz * sx),
+            Vec3::new(sz * cy, sz * sy * sx + cz * cx, sz * sy * cx - cz * sx),
+            Vec3::new(-sy, cy * sx, cy * cx),
+        ]
+    }
+}
+
+/// Places a hittable in the scene with a transform
+///
+/// Rays are taken into the object's space rather than moving the object, so
+/// any hittable can be transformed.
+pub struct Transformed<H> {
+    object: H,
+    transform: Transform,
+    rows: [Vec3; 3],
+}
+
+impl<H: Hittable> Transformed<H> {
+    /// Panics if the scale is not positive
+    pub fn new(object: H, transform: Transform) -> Self {
+        assert!(transform.scale > 0., "transform scale {} is not positive", transform.scale);
+        Self { object, transform, rows: transform.rotation_matrix() }
+    }
+
+    /// Ray in the object's space; scaling the direction along with the origin
+    /// keeps ray distances the same
+    fn to_local(&self, r: &Ray) -> Ray {
+        let t = &self.transform;
+        Ray::new(
+            self.rotate_inverse(r.origin - t.translation) / t.scale,
+            self.rotate_inverse(r.direction) / t.scale,
+        )
+    }
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    fn rotate(&self, v: Vec3) -> Vec3 {
+        Vec3::new(self.rows[0].dot(v), self.rows[1].dot(v), self.rows[2].dot(v))
+    }
+    fn rotate_inverse(&self, v: Vec3) -> Vec3 {
+        v.x() * self.rows[0] + v.y() * self.rows[1] + v.z() * self.rows[2]
+    }
+}
+
+impl<H: Hittable> Hittable for Transformed<H> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let t = &self.transform;
+        let mut rec = self.object.hit(&self.to_local(r), t_min, t_max)?;
+        rec.point = t.scale * self.rotate(rec.point) + t.translation;
+        rec.normal = self.rotate(rec.normal);
+        Some(rec)
+    }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.object.occluded(&self.to_local(r), t_min, t_max)
+    }
+    fn collect_materials(&self, materials: &mut Vec<Arc<dyn Material + Sync + Send>>) {
+        self.object.collect_materials(materials);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Point3, SimpleSphere};
+    #[test]
+    fn test_translated_scaled_sphere() {
+        let transform = Transform { translation: Vec3::new(0., 0., -5.), rotation: Vec3::new(0., 90., 0.), scale: 2. };
+        let sphere = Transformed::new(SimpleSphere::new(Point3::new(0., 0., 0.), 1.), transform);
+        let r = Ray::new(Point3::new(0., 0., 0.), Vec3::new(0., 0., -1.));
+        let rec = sphere.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.distance - 3.).abs() < 1e-9);
+        assert!((rec.point - Point3::new(0., 0., -3.)).length() < 1e-9);
+        assert!((rec.normal - Vec3::new(0., 0., 1.)).length() < 1e-9);
+    }
+    #[test]
+    #[should_panic]
+    fn test_rejects_zero_scale() {
+        Transformed::new(SimpleSphere::new(Point3::new(0., 0., 0.), 1.), Transform { scale: 0., ..Transform::default() });
+    }
+    #[test]
+    fn test_rotation() {
+        let rows = Transform { rotation: Vec3::new(0., 0., 90.), ..Transform::default() }.rotation_matrix();
+        let x = Vec3::new(1., 0., 0.);
+        let rotated = Vec3::new(rows[0].dot(x), rows[1].dot(x), rows[2].dot(x));
+        assert!((rotated - Vec3::new(0., 1., 0.)).length() < 1e-9);
+    }
+}
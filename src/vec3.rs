@@ -67,15 +67,13 @@ impl Vec3 {
         let ib = (256f64 * clamp(b, 0., 0.999)) as u8;
         [ir, ig, ib]
     }
-    pub fn random() -> Self {
-        let mut rng = thread_rng();
+    pub fn random<R: Rng + ?Sized>(rng: &mut R) -> Self {
         Self
         {
             e: [rng.gen(), rng.gen(), rng.gen()]
         }
     }
-    pub fn random_range(min: f64, max: f64) -> Self {
-        let mut rng = thread_rng();
+    pub fn random_range<R: Rng + ?Sized>(rng: &mut R, min: f64, max: f64) -> Self {
         Self
         {
             e: [rng.gen_range(min, max),
@@ -274,7 +272,7 @@ mod tests {
         let u = Vec3::new(1., 2., 3.);
         let v = Vec3::new(4., 5., 6.);
         let result = Vec3::new(-3.0, 6.0, -3.0);
-        assert_eq!(u.cross(&v), result);
+        assert_eq!(u.cross(v), result);
     }
 
     #[test]